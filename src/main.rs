@@ -1,17 +1,39 @@
+extern crate clap;
+extern crate parity_wasm;
+extern crate pwasm_utils;
 extern crate rustc_hex;
+extern crate sha2;
+extern crate tracing;
 extern crate wasmi;
+extern crate wat;
 
-use rustc_hex::FromHex;
-use std::env::args;
+use clap::{Parser, Subcommand};
+use rustc_hex::ToHex;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
 use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
 use wasmi::memory_units::Pages;
 use wasmi::{
-    Error as InterpreterError, Externals, FuncInstance, FuncRef, ImportsBuilder, MemoryInstance,
-    MemoryRef, Module, ModuleImportResolver, ModuleInstance, NopExternals, RuntimeArgs,
-    RuntimeValue, Signature, Trap, ValueType,
+    Error as InterpreterError, Externals, FuncInstance, FuncRef, GlobalDescriptor, GlobalRef,
+    HostError, ImportResolver, MemoryDescriptor, MemoryInstance, MemoryRef, Module,
+    ModuleImportResolver, ModuleInstance, NotStartedModuleRef, RuntimeArgs, RuntimeValue,
+    Signature, StackRecycler, TableDescriptor, TableRef, Trap, TrapKind, ValueType,
 };
 
+mod metrics;
+#[cfg(test)]
+mod test_modules;
 mod types;
+use crate::metrics::Metrics;
 use crate::types::*;
 
 const LOADPRESTATE_FUNC_INDEX: usize = 0;
@@ -19,288 +41,6944 @@ const BLOCKDATASIZE_FUNC_INDEX: usize = 1;
 const BLOCKDATACOPY_FUNC_INDEX: usize = 2;
 const SAVEPOSTSTATE_FUNC_INDEX: usize = 3;
 const PUSHNEWDEPOSIT_FUNC_INDEX: usize = 4;
+const GAS_FUNC_INDEX: usize = 5;
+const BEGINDEPOSIT_FUNC_INDEX: usize = 6;
+const DEPOSITWRITE_FUNC_INDEX: usize = 7;
+const ENDDEPOSIT_FUNC_INDEX: usize = 8;
+const TIMESTAMP_FUNC_INDEX: usize = 9;
+const CALLENV_FUNC_INDEX: usize = 10;
+const BLOCKDATAEMPTY_FUNC_INDEX: usize = 11;
+const MEMSET_FUNC_INDEX: usize = 12;
+const MEMCPY_FUNC_INDEX: usize = 13;
+const RESERVEDEPOSITS_FUNC_INDEX: usize = 14;
+const HISTORICALSTATEROOT_FUNC_INDEX: usize = 15;
+const SEEDEDRANDOM_FUNC_INDEX: usize = 16;
+/// `eth2_blockDataCopy` imported with an `i32` result, returning the number of bytes copied.
+const BLOCKDATACOPYCOUNTED_FUNC_INDEX: usize = 17;
+const ABORT_FUNC_INDEX: usize = 18;
+const ENVCOUNT_FUNC_INDEX: usize = 19;
+const ASSERT_FUNC_INDEX: usize = 20;
+const READU64BE_FUNC_INDEX: usize = 21;
+const WRITEU64BE_FUNC_INDEX: usize = 22;
+const VERIFYMERKLEPROOF_FUNC_INDEX: usize = 23;
+const CODESIZE_FUNC_INDEX: usize = 24;
+const CODECOPY_FUNC_INDEX: usize = 25;
+const EMITLOG_FUNC_INDEX: usize = 26;
+const PARENTROOT_FUNC_INDEX: usize = 27;
+const MEMCMP_FUNC_INDEX: usize = 28;
+const DEPOSITSTAGEDLEN_FUNC_INDEX: usize = 29;
+const ENVEXISTS_FUNC_INDEX: usize = 30;
+const SAVEPOSTSTATEFROM_FUNC_INDEX: usize = 31;
+const BLOCKDATAROOT_FUNC_INDEX: usize = 32;
+const STORAGESLOTCOUNT_FUNC_INDEX: usize = 33;
+const GASLEFT_FUNC_INDEX: usize = 34;
+const HEXENCODE_FUNC_INDEX: usize = 35;
 
-struct Runtime<'a> {
-    pub memory: Option<MemoryRef>,
-    pre_state: &'a Bytes32,
-    block_data: &'a ShardBlockBody,
-    post_state: Bytes32,
+/// Most topics a single `eth2_emitLog` entry may carry, as for EVM logs.
+const MAX_LOG_TOPICS: u32 = 4;
+
+/// Default size of the interpreter value stack in bytes.
+const VALUE_STACK_LIMIT: usize = 1024 * 1024;
+/// Default depth of the interpreter call stack in frames.
+const CALL_STACK_LIMIT: usize = 16 * 1024;
+/// Frames the interpreter call stack holds beyond the calls bounded by `call_stack_limit`:
+/// the entry point and a host function called from the deepest frame, with some slack.
+const CALL_STACK_HEADROOM: usize = 4;
+
+/// How deeply `eth2_callEnv` invocations may nest.
+const MAX_CALL_ENV_DEPTH: usize = 8;
+
+/// Indices of registered host functions start here, after the built-in ones.
+const CUSTOM_FUNC_INDEX_BASE: usize = 1000;
+
+/// Returns the import name of a host function, as used in the execution report.
+fn host_function_name(index: usize) -> &'static str {
+    match index {
+        LOADPRESTATE_FUNC_INDEX => "eth2_loadPreState",
+        BLOCKDATASIZE_FUNC_INDEX => "eth2_blockDataSize",
+        BLOCKDATACOPY_FUNC_INDEX | BLOCKDATACOPYCOUNTED_FUNC_INDEX => "eth2_blockDataCopy",
+        SAVEPOSTSTATE_FUNC_INDEX => "eth2_savePostState",
+        PUSHNEWDEPOSIT_FUNC_INDEX => "eth2_pushNewDeposit",
+        GAS_FUNC_INDEX => "gas",
+        BEGINDEPOSIT_FUNC_INDEX => "eth2_beginDeposit",
+        DEPOSITWRITE_FUNC_INDEX => "eth2_depositWrite",
+        ENDDEPOSIT_FUNC_INDEX => "eth2_endDeposit",
+        TIMESTAMP_FUNC_INDEX => "eth2_timestamp",
+        CALLENV_FUNC_INDEX => "eth2_callEnv",
+        BLOCKDATAEMPTY_FUNC_INDEX => "eth2_blockDataEmpty",
+        MEMSET_FUNC_INDEX => "eth2_memset",
+        MEMCPY_FUNC_INDEX => "eth2_memcpy",
+        RESERVEDEPOSITS_FUNC_INDEX => "eth2_reserveDeposits",
+        HISTORICALSTATEROOT_FUNC_INDEX => "eth2_historicalStateRoot",
+        SEEDEDRANDOM_FUNC_INDEX => "eth2_seededRandom",
+        ABORT_FUNC_INDEX => "eth2_abort",
+        ENVCOUNT_FUNC_INDEX => "eth2_envCount",
+        ASSERT_FUNC_INDEX => "eth2_assert",
+        READU64BE_FUNC_INDEX => "eth2_readU64BE",
+        WRITEU64BE_FUNC_INDEX => "eth2_writeU64BE",
+        VERIFYMERKLEPROOF_FUNC_INDEX => "eth2_verifyMerkleProof",
+        CODESIZE_FUNC_INDEX => "eth2_codeSize",
+        CODECOPY_FUNC_INDEX => "eth2_codeCopy",
+        EMITLOG_FUNC_INDEX => "eth2_emitLog",
+        PARENTROOT_FUNC_INDEX => "eth2_parentRoot",
+        MEMCMP_FUNC_INDEX => "eth2_memcmp",
+        DEPOSITSTAGEDLEN_FUNC_INDEX => "eth2_depositStagedLen",
+        ENVEXISTS_FUNC_INDEX => "eth2_envExists",
+        SAVEPOSTSTATEFROM_FUNC_INDEX => "eth2_savePostStateFrom",
+        BLOCKDATAROOT_FUNC_INDEX => "eth2_blockDataRoot",
+        STORAGESLOTCOUNT_FUNC_INDEX => "eth2_storageSlotCount",
+        GASLEFT_FUNC_INDEX => "eth2_gasLeft",
+        HEXENCODE_FUNC_INDEX => "eth2_hexEncode",
+        _ => panic!("unknown function index"),
+    }
 }
 
-impl<'a> Runtime<'a> {
-    fn new(pre_state: &'a Bytes32, block_data: &'a ShardBlockBody) -> Runtime<'a> {
-        Runtime {
-            memory: Some(MemoryInstance::alloc(Pages(1), Some(Pages(1))).unwrap()),
-            pre_state: pre_state,
-            block_data: block_data,
-            post_state: Bytes32::default(),
+/// How `eth2_blockDataCopy` handles a range which does not fit in the block body.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CopyOverflow {
+    /// Trap with `ExecError::BlockDataOutOfBounds`.
+    #[default]
+    Strict,
+    /// Copy only the part of the range inside the body.
+    Clamp,
+}
+
+/// A registered host function, called with the script memory and its arguments.
+pub type HostFn =
+    Arc<dyn Fn(&MemoryRef, RuntimeArgs) -> Result<Option<RuntimeValue>, ExecError> + Send + Sync>;
+
+/// Host functions registered on top of the built-in ones, imported from "env" like them.
+#[derive(Default, Clone)]
+pub struct HostFunctions {
+    functions: Vec<(&'static str, Signature, HostFn)>,
+}
+
+impl HostFunctions {
+    /// Registers `function` under `name`, failing if a built-in or an earlier registration
+    /// already uses the name.
+    pub fn register_host_fn(
+        &mut self,
+        name: &'static str,
+        signature: Signature,
+        function: HostFn,
+    ) -> Result<(), ScoutError> {
+        let builtin = BUILTIN_HOST_FNS
+            .iter()
+            .any(|(builtin, ..)| *builtin == name);
+        if builtin || self.position(name).is_some() {
+            return Err(ScoutError::DuplicateHostFn(name.to_string()));
         }
+        self.functions.push((name, signature, function));
+        Ok(())
     }
 
-    fn get_post_state(&self) -> Bytes32 {
-        self.post_state
+    fn position(&self, name: &str) -> Option<usize> {
+        self.functions.iter().position(|(n, _, _)| *n == name)
     }
 }
 
-impl<'a> Externals for Runtime<'a> {
-    fn invoke_index(
-        &mut self,
-        index: usize,
-        args: RuntimeArgs,
-    ) -> Result<Option<RuntimeValue>, Trap> {
-        match index {
-            LOADPRESTATE_FUNC_INDEX => {
-                let ptr: u32 = args.nth(0);
-                println!("loadprestate to {}", ptr);
+impl fmt::Debug for HostFunctions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list()
+            .entries(self.functions.iter().map(|(name, _, _)| name))
+            .finish()
+    }
+}
 
-                // TODO: add checks for out of bounds access
-                let memory = self.memory.as_ref().expect("expects memory");
-                memory.set(ptr, &self.pre_state.bytes).unwrap();
+/// Options controlling a single execution. The default leaves the script unrestricted.
+#[derive(Clone, Debug)]
+pub struct ExecutionConfig {
+    /// When set, execution is aborted with `ExecError::Cancelled` as soon as the flag is raised.
+    pub cancel_flag: Option<Arc<AtomicBool>>,
+    /// Fail with `ExecError::UnconsumedBlockData` unless the script read every byte of the body.
+    pub reject_unconsumed_data: bool,
+    /// The slot of the block being executed.
+    pub slot: u64,
+    /// Unix time of slot 0, the base of `eth2_timestamp`.
+    pub genesis_time: u64,
+    pub seconds_per_slot: u64,
+    /// Trap when `eth2_blockDataCopy` targets a pointer not aligned to `copy_alignment` bytes.
+    pub require_aligned_copies: bool,
+    pub copy_alignment: u32,
+    /// Capacity to allocate upfront for the deposits of a script known to emit many of them.
+    pub expected_deposits: usize,
+    /// Upper bound on the deposits a single execution may emit, also capping reservations.
+    pub max_deposits: usize,
+    /// Fail with `ExecError::NoPostState` unless the script called `eth2_savePostState`.
+    pub require_post_state: bool,
+    /// What `eth2_blockDataCopy` does with a range reaching past the end of the body.
+    pub copy_overflow: CopyOverflow,
+    /// Record every host call in `ExecutionReport::trace`.
+    pub record_trace: bool,
+    /// Fail with `ExecError::FloatingPointForbidden` if the module uses floating-point
+    /// instructions or types, whose NaN bit patterns can differ across platforms.
+    pub reject_float: bool,
+    /// Candidate names of the entry point export, the first one exported is invoked.
+    pub entry_points: Vec<String>,
+    /// Fail with `ExecError::HostCallLimitExceeded` once a script makes more host calls.
+    pub max_host_calls: Option<u64>,
+    /// Host functions available to scripts in addition to the built-in ones.
+    pub host_functions: HostFunctions,
+    /// Interpreter stack limits, pinned rather than left to the wasmi defaults so execution
+    /// does not change with them. The value stack is measured in bytes, the call stack in how
+    /// deeply the functions of the script may call each other.
+    pub value_stack_limit: usize,
+    pub call_stack_limit: usize,
+    /// Fail with `ExecError::OutOfGas` once the gas charged by the injected metering exceeds it.
+    pub gas_limit: Option<u64>,
+    /// Fail with `ExecError::UnexpectedImportModule` if the module imports functions from
+    /// anywhere but the `eth2` namespace.
+    pub strict_import_namespace: bool,
+    /// Attach a `Diagnostic` with the full interpreter error to errors raised during execution,
+    /// returned as `ExecError::Diagnosed`.
+    pub capture_diagnostics: bool,
+    /// How deeply `eth2_callEnv` invocations may nest. Each one runs a fresh interpreter on the
+    /// native stack, so this is bounded separately from `call_stack_limit`.
+    pub max_call_env_depth: usize,
+    /// Run the start function of the module before the entry point. Otherwise a module with a
+    /// start function fails with `ExecError::UnexpectedStart`.
+    pub run_start: bool,
+    /// Written into the script memory at offset 0 before the start function and the entry point
+    /// run, failing with `ExecError::MemoryAccessOutOfBounds` if the memory is too small.
+    pub initial_memory: Vec<u8>,
+    /// Treat environments with empty code as no-ops leaving their state unchanged, rather than
+    /// failing with `ExecError::EmptyScript`.
+    pub skip_empty_scripts: bool,
+    /// Fail with `ExecError::TrailingBytes` if the code does not end exactly after its last
+    /// section, rather than leaving bytes which don't form a section to the decoder.
+    pub reject_trailing_bytes: bool,
+    /// Trap with `ExecError::MultiplePostStates` when a script saves a post-state a second
+    /// time, rather than keeping the last one.
+    pub single_post_state: bool,
+}
 
-                Ok(None)
-            }
-            SAVEPOSTSTATE_FUNC_INDEX => {
-                let ptr: u32 = args.nth(0);
-                println!("savepoststate from {}", ptr);
+impl Default for ExecutionConfig {
+    fn default() -> Self {
+        ExecutionConfig {
+            cancel_flag: None,
+            reject_unconsumed_data: false,
+            slot: 0,
+            genesis_time: 0,
+            seconds_per_slot: SECONDS_PER_SLOT,
+            require_aligned_copies: false,
+            copy_alignment: 8,
+            expected_deposits: 0,
+            max_deposits: DEFAULT_MAX_DEPOSITS,
+            require_post_state: false,
+            copy_overflow: CopyOverflow::Strict,
+            record_trace: false,
+            reject_float: false,
+            entry_points: vec!["main".to_string()],
+            max_host_calls: None,
+            host_functions: HostFunctions::default(),
+            value_stack_limit: VALUE_STACK_LIMIT,
+            call_stack_limit: CALL_STACK_LIMIT,
+            gas_limit: None,
+            strict_import_namespace: false,
+            capture_diagnostics: false,
+            max_call_env_depth: MAX_CALL_ENV_DEPTH,
+            run_start: false,
+            initial_memory: Vec::new(),
+            skip_empty_scripts: false,
+            reject_trailing_bytes: false,
+            single_post_state: false,
+        }
+    }
+}
 
-                // TODO: add checks for out of bounds access
-                let memory = self.memory.as_ref().expect("expects memory");
-                memory.get_into(ptr, &mut self.post_state.bytes).unwrap();
+impl ExecutionConfig {
+    pub fn with_cancel_flag(mut self, flag: Arc<AtomicBool>) -> Self {
+        self.cancel_flag = Some(flag);
+        self
+    }
 
-                Ok(None)
-            }
-            BLOCKDATASIZE_FUNC_INDEX => {
-                let ret: i32 = self.block_data.data.len() as i32;
-                println!("blockdatasize {}", ret);
-                Ok(Some(ret.into()))
-            }
-            BLOCKDATACOPY_FUNC_INDEX => {
-                let ptr: u32 = args.nth(0);
-                let offset: u32 = args.nth(1);
-                let length: u32 = args.nth(2);
-                println!(
-                    "blockdatacopy to {} from {} for {} bytes",
-                    ptr, offset, length
-                );
+    pub fn with_reject_unconsumed_data(mut self, reject: bool) -> Self {
+        self.reject_unconsumed_data = reject;
+        self
+    }
 
-                // TODO: add overflow check
-                let offset = offset as usize;
-                let length = length as usize;
+    pub fn with_slot(mut self, slot: u64) -> Self {
+        self.slot = slot;
+        self
+    }
 
-                // TODO: add checks for out of bounds access
-                let memory = self.memory.as_ref().expect("expects memory");
-                memory
-                    .set(ptr, &self.block_data.data[offset..length])
-                    .unwrap();
+    pub fn with_genesis_time(mut self, genesis_time: u64) -> Self {
+        self.genesis_time = genesis_time;
+        self
+    }
 
-                Ok(None)
-            }
-            PUSHNEWDEPOSIT_FUNC_INDEX => unimplemented!(),
-            _ => panic!("unknown function index"),
-        }
+    pub fn with_seconds_per_slot(mut self, seconds_per_slot: u64) -> Self {
+        self.seconds_per_slot = seconds_per_slot;
+        self
     }
-}
 
-struct RuntimeModuleImportResolver;
+    pub fn with_aligned_copies(mut self, alignment: u32) -> Self {
+        self.require_aligned_copies = true;
+        self.copy_alignment = alignment;
+        self
+    }
 
-impl<'a> ModuleImportResolver for RuntimeModuleImportResolver {
-    fn resolve_func(
-        &self,
-        field_name: &str,
-        _signature: &Signature,
-    ) -> Result<FuncRef, InterpreterError> {
-        let func_ref = match field_name {
-            "eth2_loadPreState" => FuncInstance::alloc_host(
-                Signature::new(&[ValueType::I32][..], None),
-                LOADPRESTATE_FUNC_INDEX,
-            ),
-            "eth2_blockDataSize" => FuncInstance::alloc_host(
-                Signature::new(&[][..], Some(ValueType::I32)),
-                BLOCKDATASIZE_FUNC_INDEX,
-            ),
-            "eth2_blockDataCopy" => FuncInstance::alloc_host(
-                Signature::new(&[ValueType::I32, ValueType::I32, ValueType::I32][..], None),
-                BLOCKDATACOPY_FUNC_INDEX,
-            ),
-            "eth2_savePostState" => FuncInstance::alloc_host(
-                Signature::new(&[ValueType::I32][..], None),
-                SAVEPOSTSTATE_FUNC_INDEX,
-            ),
-            "eth2_pushNewDeposit" => FuncInstance::alloc_host(
-                Signature::new(&[ValueType::I32][..], None),
-                PUSHNEWDEPOSIT_FUNC_INDEX,
-            ),
-            _ => {
-                return Err(InterpreterError::Function(format!(
-                    "host module doesn't export function with name {}",
-                    field_name
-                )))
-            }
-        };
-        Ok(func_ref)
+    pub fn with_expected_deposits(mut self, expected_deposits: usize) -> Self {
+        self.expected_deposits = expected_deposits;
+        self
+    }
+
+    pub fn with_max_deposits(mut self, max_deposits: usize) -> Self {
+        self.max_deposits = max_deposits;
+        self
+    }
+
+    pub fn with_require_post_state(mut self, require: bool) -> Self {
+        self.require_post_state = require;
+        self
+    }
+
+    pub fn with_copy_overflow(mut self, copy_overflow: CopyOverflow) -> Self {
+        self.copy_overflow = copy_overflow;
+        self
+    }
+
+    pub fn with_trace(mut self, record: bool) -> Self {
+        self.record_trace = record;
+        self
+    }
+
+    pub fn with_reject_float(mut self, reject: bool) -> Self {
+        self.reject_float = reject;
+        self
+    }
+
+    pub fn with_entry_points<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.entry_points = names.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn with_max_host_calls(mut self, max_host_calls: u64) -> Self {
+        self.max_host_calls = Some(max_host_calls);
+        self
+    }
+
+    pub fn with_host_functions(mut self, host_functions: HostFunctions) -> Self {
+        self.host_functions = host_functions;
+        self
+    }
+
+    pub fn with_stack_limits(mut self, value_stack_limit: usize, call_stack_limit: usize) -> Self {
+        self.value_stack_limit = value_stack_limit;
+        self.call_stack_limit = call_stack_limit;
+        self
+    }
+
+    pub fn with_gas_limit(mut self, gas_limit: u64) -> Self {
+        self.gas_limit = Some(gas_limit);
+        self
+    }
+
+    pub fn with_strict_import_namespace(mut self, strict: bool) -> Self {
+        self.strict_import_namespace = strict;
+        self
+    }
+
+    pub fn with_diagnostics(mut self, capture: bool) -> Self {
+        self.capture_diagnostics = capture;
+        self
+    }
+
+    pub fn with_max_call_env_depth(mut self, depth: usize) -> Self {
+        self.max_call_env_depth = depth;
+        self
+    }
+
+    pub fn with_run_start(mut self, run_start: bool) -> Self {
+        self.run_start = run_start;
+        self
+    }
+
+    pub fn with_initial_memory(mut self, initial_memory: Vec<u8>) -> Self {
+        self.initial_memory = initial_memory;
+        self
+    }
+
+    pub fn with_skip_empty_scripts(mut self, skip: bool) -> Self {
+        self.skip_empty_scripts = skip;
+        self
+    }
+
+    pub fn with_reject_trailing_bytes(mut self, reject: bool) -> Self {
+        self.reject_trailing_bytes = reject;
+        self
+    }
+
+    pub fn with_single_post_state(mut self, single: bool) -> Self {
+        self.single_post_state = single;
+        self
+    }
+
+    /// The deterministic time of the configured slot, derived from the genesis time.
+    fn timestamp(&self) -> u64 {
+        self.genesis_time
+            .saturating_add(self.slot.saturating_mul(self.seconds_per_slot))
     }
 }
 
-fn wasm_load_from_file(filename: &str) -> Module {
-    use std::io::prelude::*;
-    let mut file = File::open(filename).unwrap();
-    let mut buf = Vec::new();
-    file.read_to_end(&mut buf).unwrap();
-    Module::from_buffer(buf).unwrap()
+/// The outcome of a successful execution.
+#[derive(Clone, Debug)]
+pub struct ExecutionReport {
+    pub post_state: Bytes32,
+    pub deposits: Vec<Deposit>,
+    /// Gas charged by the metering instrumentation, one unit per instruction. For a module
+    /// exporting a mutable `gas` global, the amount the global was decremented by instead.
+    pub gas_used: u64,
+    /// Number of calls made to each host function, keyed by import name.
+    pub host_calls: BTreeMap<&'static str, u64>,
+    pub execution_time: Duration,
+    /// Host calls in the order they were made, recorded only under `record_trace`.
+    pub trace: Vec<HostCall>,
+    /// Events emitted through `eth2_emitLog`, in order.
+    pub logs: Vec<LogEntry>,
+    /// Largest size of the script memory in 64 KiB pages. Memory never shrinks, so this is
+    /// its size once execution finished.
+    pub peak_memory_pages: u32,
 }
 
-fn wasm_load_from_blob(buf: &[u8]) -> Module {
-    Module::from_buffer(buf).unwrap()
+/// An event emitted by a script, like an EVM log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogEntry {
+    pub topics: Vec<Bytes32>,
+    pub data: Vec<u8>,
 }
 
-const BYTES_PER_SHARD_BLOCK_BODY: usize = 16384;
-const ZERO_HASH: Bytes32 = Bytes32 { bytes: [0u8; 32] };
+/// A host call recorded under `record_trace`, with SHA-256 hashes of the whole script memory
+/// around it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HostCall {
+    pub name: &'static str,
+    pub args: Vec<RuntimeValue>,
+    pub mem_before_hash: Bytes32,
+    pub mem_after_hash: Bytes32,
+}
 
-/// These are Phase 0 structures.
-/// https://github.com/ethereum/eth2.0-specs/blob/dev/specs/core/0_beacon-chain.md
-#[derive(Default, Clone, Debug)]
-pub struct Deposit {}
+/// The shard context visible to a script: the execution environments it can reach through
+/// `eth2_callEnv` and the recent shard state roots.
+#[derive(Clone, Copy, Debug)]
+pub struct CallContext<'a> {
+    pub scripts: &'a [ExecutionScript],
+    /// The current state of each environment, used as the pre-state of a called environment.
+    pub states: &'a dyn EnvStateStore,
+    /// State roots of the most recent slots, oldest first.
+    pub latest_state_roots: &'a [Bytes32],
+    /// Root of the last block applied to the shard.
+    pub parent_root: Bytes32,
+    /// Number of `eth2_callEnv` invocations leading to the current execution.
+    pub depth: usize,
+}
 
-/// These are Phase 2 Proposal 2 structures.
+/// The states of a context without environments.
+static NO_ENV_STATES: Vec<Bytes32> = Vec::new();
 
-#[derive(Default, Clone, Debug)]
-pub struct ExecutionScript {
-    code: Vec<u8>,
+impl<'a> Default for CallContext<'a> {
+    fn default() -> Self {
+        CallContext {
+            scripts: &[],
+            states: &NO_ENV_STATES,
+            latest_state_roots: &[],
+            parent_root: Bytes32::default(),
+            depth: 0,
+        }
+    }
 }
 
-#[derive(Default, Clone, Debug)]
-pub struct BeaconState {
-    execution_scripts: Vec<ExecutionScript>,
+/// Read-only access to the block body. Host functions read the body only through this view,
+/// which never hands out a mutable reference to it.
+#[derive(Clone, Copy, Debug)]
+struct BlockDataView<'a> {
+    data: &'a [u8],
 }
 
-/// Shards are Phase 1 structures.
-/// https://github.com/ethereum/eth2.0-specs/blob/dev/specs/core/1_shard-data-chains.md
+impl<'a> BlockDataView<'a> {
+    fn new(body: &'a ShardBlockBody) -> BlockDataView<'a> {
+        BlockDataView { data: &body.data }
+    }
 
-#[derive(Default, Clone, Debug)]
-pub struct ShardBlockHeader {}
+    fn len(&self) -> usize {
+        self.data.len()
+    }
 
-#[derive(Default, Clone, Debug)]
-pub struct ShardBlockBody {
-    data: Vec<u8>,
-}
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
 
-#[derive(Default, Clone, Debug)]
-pub struct ShardBlock {
-    env: u64, // This is added by Phase 2 Proposal 2
-    data: ShardBlockBody,
-    // TODO: add missing fields
+    /// Points the view at another body, as a future refactor might without refreshing a size
+    /// it cached.
+    #[cfg(all(test, debug_assertions))]
+    fn set_data(&mut self, data: &'a [u8]) {
+        self.data = data;
+    }
+
+    /// Returns the `length` bytes at `offset`, or `None` if the range leaves the body.
+    fn get(&self, offset: usize, length: usize) -> Option<&'a [u8]> {
+        let end = offset.checked_add(length)?;
+        self.data.get(offset..end)
+    }
+
+    /// Returns the bytes from `start` to `end`, telling a range which ends before it starts
+    /// apart from one which leaves the body.
+    fn range(&self, start: usize, end: usize) -> Result<&'a [u8], ExecError> {
+        if start > end {
+            return Err(ExecError::ReversedBlockDataRange(start, end));
+        }
+        self.get(start, end - start)
+            .ok_or(ExecError::BlockDataOutOfBounds)
+    }
 }
 
-#[derive(Default, Clone, Debug)]
-pub struct ShardState {
-    exec_env_states: Vec<Bytes32>,
-    slot: u64,
-    parent_block: ShardBlockHeader,
-    // TODO: add missing field
-    // latest_state_roots: [bytes32, LATEST_STATE_ROOTS_LEMGTH]
+struct Runtime<'a> {
+    pub memory: Option<MemoryRef>,
+    /// The code of the running script, as given and not as instrumented for metering.
+    code: &'a [u8],
+    pre_state: &'a Bytes32,
+    block_data: BlockDataView<'a>,
+    /// SHA-256 of the block body, computed on the first `eth2_blockDataRoot` call.
+    block_data_root: Option<Bytes32>,
+    /// Set only once the script saves a post-state, see `get_post_state`.
+    post_state: Option<Bytes32>,
+    deposits: Vec<Deposit>,
+    /// Receives each deposit as soon as it is pushed, in addition to `deposits`.
+    deposit_sink: Option<&'a mut dyn FnMut(Deposit)>,
+    /// Bytes of the deposit being assembled between `beginDeposit` and `endDeposit`.
+    deposit_staging: Option<Vec<u8>>,
+    gas_used: u64,
+    host_calls: BTreeMap<&'static str, u64>,
+    total_host_calls: u64,
+    trace: Vec<HostCall>,
+    logs: Vec<LogEntry>,
+    /// Which bytes of the block body were copied, tracked only under `reject_unconsumed_data`.
+    block_data_consumed: Vec<bool>,
+    config: &'a ExecutionConfig,
+    context: CallContext<'a>,
 }
 
-pub fn execute_code(
-    code: &[u8],
-    pre_state: &Bytes32,
-    block_data: &ShardBlockBody,
-) -> (Bytes32, Vec<Deposit>) {
-    println!(
-        "Executing codesize({}) and data: {:#?}",
-        code.len(),
-        block_data
-    );
+impl<'a> Runtime<'a> {
+    fn new(
+        code: &'a [u8],
+        pre_state: &'a Bytes32,
+        block_data: &'a ShardBlockBody,
+        config: &'a ExecutionConfig,
+        context: CallContext<'a>,
+    ) -> Result<Runtime<'a>, ExecError> {
+        Ok(Runtime {
+            memory: Some(alloc_memory(Pages(1), Some(Pages(1)))?),
+            code,
+            pre_state,
+            block_data: BlockDataView::new(block_data),
+            block_data_root: None,
+            post_state: None,
+            deposits: Vec::with_capacity(config.expected_deposits.min(config.max_deposits)),
+            deposit_sink: None,
+            deposit_staging: None,
+            gas_used: 0,
+            host_calls: BTreeMap::new(),
+            total_host_calls: 0,
+            trace: Vec::new(),
+            logs: Vec::new(),
+            block_data_consumed: if config.reject_unconsumed_data {
+                vec![false; block_data.data.len()]
+            } else {
+                Vec::new()
+            },
+            config,
+            context,
+        })
+    }
 
-    let module = wasm_load_from_blob(&code);
-    let mut imports = ImportsBuilder::new();
-    // FIXME: use eth2
-    imports.push_resolver("env", &RuntimeModuleImportResolver);
+    /// The saved post-state or, for a script which never saved one, the pre-state, so that
+    /// not saving leaves the state unchanged rather than zeroing it.
+    fn get_post_state(&self) -> Bytes32 {
+        self.post_state.unwrap_or(*self.pre_state)
+    }
 
-    let instance = ModuleInstance::new(&module, &imports)
-        .unwrap()
-        .assert_no_start();
+    /// Reads `length` bytes at `ptr` from the script memory, failing if the range is out of bounds.
+    fn read_memory(&self, ptr: u32, length: u32) -> Result<Vec<u8>, ExecError> {
+        let memory = self.memory.as_ref().expect("expects memory");
+        let data = memory
+            .get(ptr, length as usize)
+            .map_err(|_| ExecError::MemoryAccessOutOfBounds)?;
+        debug_assert_eq!(data.len(), length as usize, "short memory read");
+        debug_assert_in_bounds(memory, ptr, data.len());
+        Ok(data)
+    }
 
-    let mut runtime = Runtime::new(pre_state, block_data);
+    /// Reads a message of `length` bytes at `ptr`, replacing invalid UTF-8.
+    fn read_string(&self, ptr: u32, length: u32) -> Result<String, ExecError> {
+        let data = self.read_memory(ptr, length)?;
+        Ok(String::from_utf8_lossy(&data).into_owned())
+    }
 
-    let internal_mem = instance
-        .export_by_name("memory")
-        .expect("Module expected to have 'memory' export")
-        .as_memory()
-        .cloned()
-        .expect("'memory' export should be a memory");
+    /// Writes the state to `ptr`, taking as many bytes as the state holds.
+    fn load_state<S: AsRef<[u8]>>(&self, ptr: u32, state: &S) -> Result<(), ExecError> {
+        self.write_memory(ptr, state.as_ref())
+    }
 
-    runtime.memory = Some(internal_mem);
+    /// Fills the state from `ptr`, reading as many bytes as the state holds.
+    fn save_state<S: AsMut<[u8]>>(&self, ptr: u32, state: &mut S) -> Result<(), ExecError> {
+        let state = state.as_mut();
+        let data = self.read_memory(ptr, state.len() as u32)?;
+        state.copy_from_slice(&data);
+        Ok(())
+    }
 
-    let result = instance
-        .invoke_export("main", &[], &mut runtime)
-        .expect("Executed 'main'");
+    /// Writes `data` at `ptr` into the script memory, failing if the range is out of bounds.
+    fn write_memory(&self, ptr: u32, data: &[u8]) -> Result<(), ExecError> {
+        let memory = self.memory.as_ref().expect("expects memory");
+        memory
+            .set(ptr, data)
+            .map_err(|_| ExecError::MemoryAccessOutOfBounds)?;
+        debug_assert_in_bounds(memory, ptr, data.len());
+        Ok(())
+    }
+
+    fn memory_hash(&self) -> Bytes32 {
+        let memory = self.memory.as_ref().expect("expects memory");
+        let digest = memory.with_direct_access(|buf| Sha256::digest(buf));
+        Bytes32::from(<[u8; 32]>::from(digest))
+    }
+
+    fn set_post_state(&mut self, post_state: Bytes32) -> Result<(), ExecError> {
+        if self.config.single_post_state && self.post_state.is_some() {
+            return Err(ExecError::MultiplePostStates);
+        }
+        self.post_state = Some(post_state);
+        Ok(())
+    }
 
-    println!("Result: {:?}", result);
-    println!("Execution finished");
+    fn push_deposit(&mut self, deposit: Deposit) -> Result<(), ExecError> {
+        if self.deposits.len() >= self.config.max_deposits {
+            return Err(ExecError::DepositLimitExceeded);
+        }
+        if let Some(sink) = &mut self.deposit_sink {
+            sink(deposit.clone());
+        }
+        self.deposits.push(deposit);
+        Ok(())
+    }
 
-    (runtime.get_post_state(), vec![Deposit {}])
+    fn block_data_fully_consumed(&self) -> bool {
+        self.block_data_consumed.iter().all(|&consumed| consumed)
+    }
 }
 
-pub fn process_shard_block(
-    state: &mut ShardState,
-    beacon_state: BeaconState,
-    block: Option<ShardBlock>,
-) {
-    // println!("Beacon state: {:#?}", beacon_state);
-    println!("Executing block: {:#?}", block);
+/// Extracts the memory pointer passed as argument `n`.
+///
+/// The ABI passes pointers as `i32`, which are reinterpreted as unsigned offsets into the memory,
+/// the same way WASM load and store instructions treat addresses.
+fn arg_ptr(args: &RuntimeArgs, n: usize) -> u32 {
+    args.nth::<u32>(n)
+}
 
-    println!("Pre-execution: {:#?}", state);
+/// Extracts the length passed as argument `n`. Lengths, offsets into host data and other
+/// counts are passed as `i32` and reinterpreted as unsigned, so they are never negative.
+fn arg_len(args: &RuntimeArgs, n: usize) -> u32 {
+    args.nth::<u32>(n)
+}
 
-    // TODO: implement state root handling
+/// Extracts the byte passed as argument `n`, which is the low byte of the `i32` value.
+fn arg_byte(args: &RuntimeArgs, n: usize) -> u8 {
+    args.nth::<u32>(n) as u8
+}
 
-    if let Some(block) = block {
-        // The execution environment identifier
-        let env = block.env as usize; // FIXME: usize can be 32-bit
-        let code = &beacon_state.execution_scripts[env].code;
+/// A deterministic byte stream of SHA-256 in counter mode, seeded by `pre_state || slot`.
+fn seeded_random_bytes(pre_state: &Bytes32, slot: u64, length: usize) -> Vec<u8> {
+    let mut stream = Vec::with_capacity(length);
+    let mut counter = 0u64;
+    while stream.len() < length {
+        let block = Sha256::new()
+            .chain_update(pre_state.bytes)
+            .chain_update(slot.to_le_bytes())
+            .chain_update(counter.to_le_bytes())
+            .finalize();
+        let take = (length - stream.len()).min(block.len());
+        stream.extend_from_slice(&block[..take]);
+        counter += 1;
+    }
+    stream
+}
 
-        // Set post states to empty for any holes
-        for x in 0..env {
-            state.exec_env_states.push(ZERO_HASH)
-        }
-        let pre_state = &state.exec_env_states[env];
-        let (post_state, deposits) = execute_code(code, pre_state, &block.data);
-        state.exec_env_states[env] = post_state
+/// Checks a SHA-256 merkle branch of concatenated 32 byte nodes for the leaf at `index`
+/// against `root`, as `is_valid_merkle_branch` in the beacon chain spec.
+fn verify_merkle_branch(leaf: &[u8], branch: &[u8], index: u32, root: &[u8]) -> bool {
+    let mut node = leaf.to_vec();
+    for (depth, sibling) in branch.chunks(32).enumerate() {
+        let (left, right) = if index.checked_shr(depth as u32).unwrap_or(0) & 1 == 1 {
+            (sibling, &node[..])
+        } else {
+            (&node[..], sibling)
+        };
+        node = Sha256::new()
+            .chain_update(left)
+            .chain_update(right)
+            .finalize()
+            .to_vec();
     }
+    node == root
+}
 
-    // TODO: implement state + deposit root handling
+/// Allocates a memory on the host side, failing instead of panicking if wasmi can't.
+fn alloc_memory(initial: Pages, maximum: Option<Pages>) -> Result<MemoryRef, ExecError> {
+    MemoryInstance::alloc(initial, maximum).map_err(|_| ExecError::MemoryAllocationFailed)
+}
 
-    println!("Post-execution: {:#?}", state)
+/// Current size of the memory in bytes.
+fn memory_size_bytes(memory: &MemoryRef) -> usize {
+    memory.current_size().0 * WASM_PAGE_SIZE
 }
 
-fn load_file(filename: &str) -> Vec<u8> {
-    use std::io::prelude::*;
-    let mut file = File::open(filename).unwrap();
-    let mut buf = Vec::new();
-    file.read_to_end(&mut buf).unwrap();
-    buf
+/// Checks the size reported by `eth2_blockDataSize` against the body at the time of the call,
+/// in case the size is ever cached.
+fn debug_assert_block_data_size(size: i32, data: &[u8]) {
+    debug_assert_eq!(
+        size as usize,
+        data.len(),
+        "eth2_blockDataSize reported {} bytes for a body of {}",
+        size,
+        data.len()
+    );
 }
 
-fn main() {
-    let execution_script = load_file("phase2_helloworld.wasm");
+/// Re-checks that a completed access of `length` bytes at `ptr` lies within the memory. This
+/// duplicates the bounds checks of the accessors, to catch mistakes in the range math of the
+/// host functions during development.
+fn debug_assert_in_bounds(memory: &MemoryRef, ptr: u32, length: usize) {
+    let size = memory_size_bytes(memory);
+    debug_assert!(
+        (ptr as usize)
+            .checked_add(length)
+            .is_some_and(|end| end <= size),
+        "memory access of {} bytes at {} exceeds memory size {}",
+        length,
+        ptr,
+        size
+    );
+}
 
-    let mut shard_state = ShardState {
-        exec_env_states: vec![Bytes32::default()],
-        slot: 0,
-        parent_block: ShardBlockHeader {},
-    };
-    let beacon_state = BeaconState {
-        execution_scripts: vec![
-            ExecutionScript {
-                code: execution_script.to_vec(),
-            },
-            ExecutionScript {
-                code: execution_script.to_vec(),
-            },
-        ],
-    };
-    let shard_block = ShardBlock {
-        env: 1,
-        data: ShardBlockBody { data: vec![] },
-    };
-    process_shard_block(&mut shard_state, beacon_state, Some(shard_block))
+impl<'a> Externals for Runtime<'a> {
+    fn invoke_index(
+        &mut self,
+        index: usize,
+        args: RuntimeArgs,
+    ) -> Result<Option<RuntimeValue>, Trap> {
+        if index != GAS_FUNC_INDEX {
+            *self
+                .host_calls
+                .entry(self.function_name(index))
+                .or_insert(0) += 1;
+            self.total_host_calls += 1;
+            if let Some(max_host_calls) = self.config.max_host_calls {
+                if self.total_host_calls > max_host_calls {
+                    return Err(ExecError::HostCallLimitExceeded.into());
+                }
+            }
+        }
+
+        // The metering calls are not part of the script behaviour and stay out of the trace.
+        if self.config.record_trace && index != GAS_FUNC_INDEX {
+            let args_before = args.as_ref().to_vec();
+            let mem_before_hash = self.memory_hash();
+            let result = self.dispatch(index, args);
+            self.trace.push(HostCall {
+                name: self.function_name(index),
+                args: args_before,
+                mem_before_hash,
+                mem_after_hash: self.memory_hash(),
+            });
+            return result;
+        }
+        self.dispatch(index, args)
+    }
+}
+
+impl<'a> Runtime<'a> {
+    fn function_name(&self, index: usize) -> &'static str {
+        match index.checked_sub(CUSTOM_FUNC_INDEX_BASE) {
+            Some(custom) => self.config.host_functions.functions[custom].0,
+            None => host_function_name(index),
+        }
+    }
+
+    fn dispatch(&mut self, index: usize, args: RuntimeArgs) -> Result<Option<RuntimeValue>, Trap> {
+        match index {
+            LOADPRESTATE_FUNC_INDEX => {
+                let ptr = arg_ptr(&args, 0);
+                debug!(ptr, "eth2_loadPreState");
+
+                self.load_state(ptr, self.pre_state)?;
+
+                Ok(None)
+            }
+            SAVEPOSTSTATE_FUNC_INDEX => {
+                let ptr = arg_ptr(&args, 0);
+                debug!(ptr, "eth2_savePostState");
+
+                let mut post_state = Bytes32::default();
+                self.save_state(ptr, &mut post_state)?;
+                self.set_post_state(post_state)?;
+
+                Ok(None)
+            }
+            BLOCKDATASIZE_FUNC_INDEX => {
+                let ret: i32 = self.block_data.len() as i32;
+                debug!(size = ret, "eth2_blockDataSize");
+                debug_assert_block_data_size(ret, self.block_data.data);
+                Ok(Some(ret.into()))
+            }
+            BLOCKDATACOPY_FUNC_INDEX | BLOCKDATACOPYCOUNTED_FUNC_INDEX => {
+                let ptr = arg_ptr(&args, 0);
+                let offset = arg_len(&args, 1);
+                let length = arg_len(&args, 2);
+                debug!(ptr, offset, length, "eth2_blockDataCopy");
+
+                if self.config.require_aligned_copies
+                    && !ptr.is_multiple_of(self.config.copy_alignment)
+                {
+                    return Err(ExecError::UnalignedCopy(ptr).into());
+                }
+
+                let (start, end) = match self.config.copy_overflow {
+                    // The end is computed in the 32-bit range of the ABI, so a length which
+                    // wraps it ends up before the start.
+                    CopyOverflow::Strict => (offset as usize, offset.wrapping_add(length) as usize),
+                    CopyOverflow::Clamp => {
+                        let start = (offset as usize).min(self.block_data.len());
+                        let length = (length as usize).min(self.block_data.len() - start);
+                        (start, start + length)
+                    }
+                };
+                let data = self.block_data.range(start, end)?;
+
+                self.write_memory(ptr, data)?;
+
+                if self.config.reject_unconsumed_data {
+                    for consumed in &mut self.block_data_consumed[start..start + data.len()] {
+                        *consumed = true;
+                    }
+                }
+
+                if index == BLOCKDATACOPYCOUNTED_FUNC_INDEX {
+                    Ok(Some(RuntimeValue::I32(data.len() as i32)))
+                } else {
+                    Ok(None)
+                }
+            }
+            PUSHNEWDEPOSIT_FUNC_INDEX => {
+                let ptr = arg_ptr(&args, 0);
+                debug!(ptr, "eth2_pushNewDeposit");
+
+                let data = self.read_memory(ptr, DEPOSIT_DATA_SIZE as u32)?;
+                let deposit = Deposit::from_bytes(&data).expect("read a full deposit");
+                self.push_deposit(deposit)?;
+
+                Ok(None)
+            }
+            BEGINDEPOSIT_FUNC_INDEX => {
+                debug!("eth2_beginDeposit");
+
+                if self.deposit_staging.is_some() {
+                    return Err(
+                        ExecError::InvalidDeposit("deposit already in progress".into()).into(),
+                    );
+                }
+                self.deposit_staging = Some(Vec::with_capacity(DEPOSIT_DATA_SIZE));
+
+                Ok(None)
+            }
+            DEPOSITWRITE_FUNC_INDEX => {
+                let ptr = arg_ptr(&args, 0);
+                let length = arg_len(&args, 1);
+                debug!(ptr, length, "eth2_depositWrite");
+
+                let data = self.read_memory(ptr, length)?;
+                let staging = self
+                    .deposit_staging
+                    .as_mut()
+                    .ok_or_else(|| ExecError::InvalidDeposit("no deposit in progress".into()))?;
+                if staging.len() + data.len() > DEPOSIT_DATA_SIZE {
+                    return Err(ExecError::InvalidDeposit("deposit data overflow".into()).into());
+                }
+                staging.extend_from_slice(&data);
+
+                Ok(None)
+            }
+            ENDDEPOSIT_FUNC_INDEX => {
+                debug!("eth2_endDeposit");
+
+                let staging = self
+                    .deposit_staging
+                    .take()
+                    .ok_or_else(|| ExecError::InvalidDeposit("no deposit in progress".into()))?;
+                let deposit = Deposit::from_bytes(&staging)
+                    .ok_or_else(|| ExecError::InvalidDeposit("incomplete deposit data".into()))?;
+                self.push_deposit(deposit)?;
+
+                Ok(None)
+            }
+            TIMESTAMP_FUNC_INDEX => {
+                let ret = self.config.timestamp() as i64;
+                debug!(timestamp = ret, "eth2_timestamp");
+                Ok(Some(ret.into()))
+            }
+            CALLENV_FUNC_INDEX => {
+                let env = arg_len(&args, 0);
+                let input_ptr = arg_ptr(&args, 1);
+                let input_len = arg_len(&args, 2);
+                let output_ptr = arg_ptr(&args, 3);
+                let output_max = arg_len(&args, 4);
+                debug!(
+                    env,
+                    input_ptr, input_len, output_ptr, output_max, "eth2_callEnv"
+                );
+
+                if self.context.depth >= self.config.max_call_env_depth {
+                    return Err(ExecError::EnvCallDepthExceeded.into());
+                }
+                let script = self
+                    .context
+                    .scripts
+                    .get(env as usize)
+                    .ok_or(ExecError::UnknownEnvironment(u64::from(env)))?;
+                let pre_state = self.context.states.get(env as usize);
+                let input = ShardBlockBody {
+                    data: self.read_memory(input_ptr, input_len)?.into(),
+                };
+                let context = CallContext {
+                    depth: self.context.depth + 1,
+                    ..self.context
+                };
+                // The callee runs on the gas and host calls the caller has left.
+                let mut config = self.config.clone();
+                config.gas_limit = self
+                    .config
+                    .gas_limit
+                    .map(|limit| limit.saturating_sub(self.gas_used));
+                config.max_host_calls = self
+                    .config
+                    .max_host_calls
+                    .map(|max| max.saturating_sub(self.total_host_calls));
+
+                let report =
+                    execute_code_in_context(&script.code, &pre_state, &input, &config, context)?;
+                self.gas_used += report.gas_used;
+                if self
+                    .config
+                    .gas_limit
+                    .is_some_and(|limit| self.gas_used > limit)
+                {
+                    return Err(ExecError::OutOfGas.into());
+                }
+                for (name, calls) in report.host_calls {
+                    *self.host_calls.entry(name).or_insert(0) += calls;
+                    self.total_host_calls += calls;
+                }
+                self.logs.extend(report.logs);
+                for deposit in report.deposits {
+                    self.push_deposit(deposit)?;
+                }
+
+                // Like EVM return data, the full length is returned even if the output is truncated.
+                let output = &report.post_state.bytes;
+                let written = output.len().min(output_max as usize);
+                self.write_memory(output_ptr, &output[..written])?;
+
+                Ok(Some(RuntimeValue::I32(output.len() as i32)))
+            }
+            BLOCKDATAEMPTY_FUNC_INDEX => {
+                let ret = self.block_data.is_empty() as i32;
+                debug!(empty = ret, "eth2_blockDataEmpty");
+                Ok(Some(ret.into()))
+            }
+            MEMSET_FUNC_INDEX => {
+                let ptr = arg_ptr(&args, 0);
+                let value = arg_byte(&args, 1);
+                let length = arg_len(&args, 2);
+                debug!(ptr, value, length, "eth2_memset");
+
+                let memory = self.memory.as_ref().expect("expects memory");
+                memory
+                    .clear(ptr as usize, value, length as usize)
+                    .map_err(|_| ExecError::MemoryAccessOutOfBounds)?;
+                debug_assert_in_bounds(memory, ptr, length as usize);
+
+                Ok(None)
+            }
+            MEMCPY_FUNC_INDEX => {
+                let dst = arg_ptr(&args, 0);
+                let src = arg_ptr(&args, 1);
+                let length = arg_len(&args, 2);
+                debug!(dst, src, length, "eth2_memcpy");
+
+                // Overlapping regions are handled like memmove.
+                let memory = self.memory.as_ref().expect("expects memory");
+                memory
+                    .copy(src as usize, dst as usize, length as usize)
+                    .map_err(|_| ExecError::MemoryAccessOutOfBounds)?;
+                debug_assert_in_bounds(memory, src, length as usize);
+                debug_assert_in_bounds(memory, dst, length as usize);
+
+                Ok(None)
+            }
+            RESERVEDEPOSITS_FUNC_INDEX => {
+                let count = arg_len(&args, 0);
+                debug!(count, "eth2_reserveDeposits");
+
+                // Capped, so a bogus count from the script can not cause a huge allocation.
+                let target = (count as usize).min(self.config.max_deposits);
+                self.deposits
+                    .reserve_exact(target.saturating_sub(self.deposits.len()));
+
+                Ok(None)
+            }
+            HISTORICALSTATEROOT_FUNC_INDEX => {
+                let slots_ago = arg_len(&args, 0);
+                let ptr = arg_ptr(&args, 1);
+                debug!(slots_ago, ptr, "eth2_historicalStateRoot");
+
+                // The most recent root is the last one, recorded at the end of the previous slot.
+                let roots = self.context.latest_state_roots;
+                let root = (slots_ago as usize)
+                    .checked_sub(1)
+                    .and_then(|back| roots.len().checked_sub(back + 1))
+                    .map(|index| roots[index]);
+                match root {
+                    Some(root) => {
+                        self.write_memory(ptr, &root.bytes)?;
+                        Ok(Some(RuntimeValue::I32(1)))
+                    }
+                    None => Ok(Some(RuntimeValue::I32(0))),
+                }
+            }
+            SEEDEDRANDOM_FUNC_INDEX => {
+                let ptr = arg_ptr(&args, 0);
+                let length = arg_len(&args, 1);
+                debug!(ptr, length, "eth2_seededRandom");
+
+                // Check the range before generating the stream so a huge length cannot allocate.
+                let memory = self.memory.as_ref().expect("expects memory");
+                if (ptr as usize)
+                    .checked_add(length as usize)
+                    .is_none_or(|end| end > memory_size_bytes(memory))
+                {
+                    return Err(ExecError::MemoryAccessOutOfBounds.into());
+                }
+                let stream = seeded_random_bytes(self.pre_state, self.config.slot, length as usize);
+                self.write_memory(ptr, &stream)?;
+
+                Ok(None)
+            }
+            ABORT_FUNC_INDEX => {
+                let code = args.nth_checked::<i32>(0)?;
+                debug!(code, "eth2_abort");
+
+                Err(ExecError::Aborted(code).into())
+            }
+            ENVCOUNT_FUNC_INDEX => {
+                let ret = self.context.scripts.len() as i32;
+                debug!(count = ret, "eth2_envCount");
+                Ok(Some(ret.into()))
+            }
+            ASSERT_FUNC_INDEX => {
+                let condition = args.nth_checked::<i32>(0)?;
+                let msg_ptr = arg_ptr(&args, 1);
+                let msg_len = arg_len(&args, 2);
+                debug!(condition, msg_ptr, msg_len, "eth2_assert");
+
+                if condition == 0 {
+                    let message = self.read_string(msg_ptr, msg_len)?;
+                    return Err(ExecError::AssertionFailed(message).into());
+                }
+                Ok(None)
+            }
+            READU64BE_FUNC_INDEX => {
+                let ptr = arg_ptr(&args, 0);
+                debug!(ptr, "eth2_readU64BE");
+
+                // The u64 is returned with the same bits as an i64, the only 64-bit WASM integer.
+                let data = self.read_memory(ptr, 8)?;
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(&data);
+                Ok(Some(RuntimeValue::I64(u64::from_be_bytes(bytes) as i64)))
+            }
+            WRITEU64BE_FUNC_INDEX => {
+                let ptr = arg_ptr(&args, 0);
+                let value = args.nth_checked::<i64>(1)? as u64;
+                debug!(ptr, value, "eth2_writeU64BE");
+
+                self.write_memory(ptr, &value.to_be_bytes())?;
+                Ok(None)
+            }
+            VERIFYMERKLEPROOF_FUNC_INDEX => {
+                let leaf_ptr = arg_ptr(&args, 0);
+                let proof_ptr = arg_ptr(&args, 1);
+                let proof_len = arg_len(&args, 2);
+                let index = arg_len(&args, 3);
+                let root_ptr = arg_ptr(&args, 4);
+                debug!(
+                    leaf_ptr,
+                    proof_ptr, proof_len, index, root_ptr, "eth2_verifyMerkleProof"
+                );
+
+                // The proof length counts 32 byte nodes, from the leaf level up.
+                let proof_bytes = proof_len
+                    .checked_mul(32)
+                    .ok_or(ExecError::MemoryAccessOutOfBounds)?;
+                let leaf = self.read_memory(leaf_ptr, 32)?;
+                let proof = self.read_memory(proof_ptr, proof_bytes)?;
+                let root = self.read_memory(root_ptr, 32)?;
+                let valid = verify_merkle_branch(&leaf, &proof, index, &root);
+                Ok(Some(RuntimeValue::I32(valid as i32)))
+            }
+            CODESIZE_FUNC_INDEX => {
+                let ret = self.code.len() as i32;
+                debug!(size = ret, "eth2_codeSize");
+                Ok(Some(ret.into()))
+            }
+            CODECOPY_FUNC_INDEX => {
+                let ptr = arg_ptr(&args, 0);
+                let offset = arg_len(&args, 1);
+                let length = arg_len(&args, 2);
+                debug!(ptr, offset, length, "eth2_codeCopy");
+
+                let code = (offset as usize)
+                    .checked_add(length as usize)
+                    .and_then(|end| self.code.get(offset as usize..end))
+                    .ok_or(ExecError::CodeOutOfBounds)?;
+                self.write_memory(ptr, code)?;
+                Ok(None)
+            }
+            EMITLOG_FUNC_INDEX => {
+                let topics_ptr = arg_ptr(&args, 0);
+                let topics_count = arg_len(&args, 1);
+                let data_ptr = arg_ptr(&args, 2);
+                let data_len = arg_len(&args, 3);
+                debug!(topics_ptr, topics_count, data_ptr, data_len, "eth2_emitLog");
+
+                if topics_count > MAX_LOG_TOPICS {
+                    return Err(ExecError::TooManyLogTopics(topics_count).into());
+                }
+                let topics = self
+                    .read_memory(topics_ptr, topics_count * 32)?
+                    .chunks(32)
+                    .map(|topic| {
+                        let mut bytes = [0u8; 32];
+                        bytes.copy_from_slice(topic);
+                        Bytes32::from(bytes)
+                    })
+                    .collect();
+                let data = self.read_memory(data_ptr, data_len)?;
+                self.logs.push(LogEntry { topics, data });
+                Ok(None)
+            }
+            PARENTROOT_FUNC_INDEX => {
+                let ptr = arg_ptr(&args, 0);
+                debug!(ptr, "eth2_parentRoot");
+
+                let root = self.context.parent_root;
+                self.write_memory(ptr, &root.bytes)?;
+                Ok(None)
+            }
+            MEMCMP_FUNC_INDEX => {
+                let a = arg_ptr(&args, 0) as usize;
+                let b = arg_ptr(&args, 1) as usize;
+                let length = arg_len(&args, 2) as usize;
+                debug!(a, b, length, "eth2_memcmp");
+
+                // Compared in place, both regions must be in bounds whether or not they match.
+                let memory = self.memory.as_ref().expect("expects memory");
+                let equal = memory
+                    .with_direct_access(|buf| {
+                        let a = buf.get(a..a.checked_add(length)?)?;
+                        let b = buf.get(b..b.checked_add(length)?)?;
+                        Some(a == b)
+                    })
+                    .ok_or(ExecError::MemoryAccessOutOfBounds)?;
+                Ok(Some(RuntimeValue::I32(if equal { 0 } else { 1 })))
+            }
+            DEPOSITSTAGEDLEN_FUNC_INDEX => {
+                // Zero outside of a deposit, like a deposit with nothing written yet.
+                let ret = self.deposit_staging.as_ref().map_or(0, Vec::len) as i32;
+                debug!(length = ret, "eth2_depositStagedLen");
+                Ok(Some(ret.into()))
+            }
+            ENVEXISTS_FUNC_INDEX => {
+                let env = arg_len(&args, 0);
+                let exists = (env as usize) < self.context.scripts.len();
+                debug!(env, exists, "eth2_envExists");
+                Ok(Some(RuntimeValue::I32(exists as i32)))
+            }
+            SAVEPOSTSTATEFROM_FUNC_INDEX => {
+                let ptr = arg_ptr(&args, 0);
+                let length = arg_len(&args, 1);
+                debug!(ptr, length, "eth2_savePostStateFrom");
+
+                // States are fixed at 32 bytes, so any other length is rejected rather than
+                // padded or truncated.
+                let mut post_state = Bytes32::default();
+                if length as usize != post_state.bytes.len() {
+                    return Err(ExecError::BadPostStateLength(length).into());
+                }
+                self.save_state(ptr, &mut post_state)?;
+                self.set_post_state(post_state)?;
+
+                Ok(None)
+            }
+            BLOCKDATAROOT_FUNC_INDEX => {
+                let ptr = arg_ptr(&args, 0);
+                debug!(ptr, "eth2_blockDataRoot");
+
+                let data = self.block_data.range(0, self.block_data.len())?;
+                let root = *self
+                    .block_data_root
+                    .get_or_insert_with(|| Sha256Hasher.hash(data));
+                self.write_memory(ptr, &root.bytes)?;
+                Ok(None)
+            }
+            STORAGESLOTCOUNT_FUNC_INDEX => {
+                debug!("eth2_storageSlotCount");
+
+                // Each environment stores a single `Bytes32` root, its pre-state.
+                Ok(Some(RuntimeValue::I32(1)))
+            }
+            GASLEFT_FUNC_INDEX => {
+                // The metering pass charges a whole block on entering it, so the instructions
+                // still to run in the current block are already deducted.
+                let ret = match self.config.gas_limit {
+                    Some(limit) => limit.saturating_sub(self.gas_used).min(i64::MAX as u64) as i64,
+                    None => i64::MAX,
+                };
+                debug!(gas_left = ret, "eth2_gasLeft");
+                Ok(Some(ret.into()))
+            }
+            HEXENCODE_FUNC_INDEX => {
+                let src_ptr = arg_ptr(&args, 0);
+                let src_len = arg_len(&args, 1);
+                let dst_ptr = arg_ptr(&args, 2);
+                let dst_max = arg_len(&args, 3);
+                debug!(src_ptr, src_len, dst_ptr, dst_max, "eth2_hexEncode");
+
+                let src = self.read_memory(src_ptr, src_len)?;
+                // Two lowercase characters per byte, without a prefix. A destination too small
+                // for all of them is left untouched.
+                if u64::from(src_len) * 2 > u64::from(dst_max) {
+                    return Ok(Some(RuntimeValue::I32(-1)));
+                }
+                let hex: String = src.to_hex();
+                self.write_memory(dst_ptr, hex.as_bytes())?;
+                Ok(Some(RuntimeValue::I32(hex.len() as i32)))
+            }
+            GAS_FUNC_INDEX => {
+                let gas = arg_len(&args, 0);
+                self.gas_used += u64::from(gas);
+                if self
+                    .config
+                    .gas_limit
+                    .is_some_and(|limit| self.gas_used > limit)
+                {
+                    return Err(ExecError::OutOfGas.into());
+                }
+
+                // Injected at the start of every block by the metering pass, which makes it
+                // the place to check for conditions that must interrupt a running script.
+                if let Some(flag) = &self.config.cancel_flag {
+                    if flag.load(Ordering::Relaxed) {
+                        return Err(ExecError::Cancelled.into());
+                    }
+                }
+                Ok(None)
+            }
+            _ if index >= CUSTOM_FUNC_INDEX_BASE => {
+                let (name, _, function) =
+                    &self.config.host_functions.functions[index - CUSTOM_FUNC_INDEX_BASE];
+                debug!(name = *name, "registered host function");
+
+                let memory = self.memory.as_ref().expect("expects memory");
+                Ok(function(memory, args)?)
+            }
+            _ => panic!("unknown function index"),
+        }
+    }
+}
+
+/// A built-in host function: its name, parameters, result and the index `invoke_index`
+/// dispatches on.
+type BuiltinHostFn = (&'static str, &'static [ValueType], Option<ValueType>, usize);
+
+/// The host functions every script can import, besides those registered in `HostFunctions`.
+/// A name may be listed more than once for variants with different results, the import picks
+/// the one matching its signature and gets the first otherwise.
+const BUILTIN_HOST_FNS: &[BuiltinHostFn] = &[
+    (
+        "eth2_loadPreState",
+        &[ValueType::I32],
+        None,
+        LOADPRESTATE_FUNC_INDEX,
+    ),
+    (
+        "eth2_blockDataSize",
+        &[],
+        Some(ValueType::I32),
+        BLOCKDATASIZE_FUNC_INDEX,
+    ),
+    (
+        "eth2_blockDataCopy",
+        &[ValueType::I32; 3],
+        None,
+        BLOCKDATACOPY_FUNC_INDEX,
+    ),
+    (
+        "eth2_blockDataCopy",
+        &[ValueType::I32; 3],
+        Some(ValueType::I32),
+        BLOCKDATACOPYCOUNTED_FUNC_INDEX,
+    ),
+    (
+        "eth2_savePostState",
+        &[ValueType::I32],
+        None,
+        SAVEPOSTSTATE_FUNC_INDEX,
+    ),
+    (
+        "eth2_pushNewDeposit",
+        &[ValueType::I32],
+        None,
+        PUSHNEWDEPOSIT_FUNC_INDEX,
+    ),
+    ("eth2_beginDeposit", &[], None, BEGINDEPOSIT_FUNC_INDEX),
+    (
+        "eth2_depositWrite",
+        &[ValueType::I32, ValueType::I32],
+        None,
+        DEPOSITWRITE_FUNC_INDEX,
+    ),
+    ("eth2_endDeposit", &[], None, ENDDEPOSIT_FUNC_INDEX),
+    (
+        "eth2_timestamp",
+        &[],
+        Some(ValueType::I64),
+        TIMESTAMP_FUNC_INDEX,
+    ),
+    (
+        "eth2_callEnv",
+        &[ValueType::I32; 5],
+        Some(ValueType::I32),
+        CALLENV_FUNC_INDEX,
+    ),
+    (
+        "eth2_blockDataEmpty",
+        &[],
+        Some(ValueType::I32),
+        BLOCKDATAEMPTY_FUNC_INDEX,
+    ),
+    ("eth2_memset", &[ValueType::I32; 3], None, MEMSET_FUNC_INDEX),
+    ("eth2_memcpy", &[ValueType::I32; 3], None, MEMCPY_FUNC_INDEX),
+    (
+        "eth2_reserveDeposits",
+        &[ValueType::I32],
+        None,
+        RESERVEDEPOSITS_FUNC_INDEX,
+    ),
+    (
+        "eth2_historicalStateRoot",
+        &[ValueType::I32; 2],
+        Some(ValueType::I32),
+        HISTORICALSTATEROOT_FUNC_INDEX,
+    ),
+    (
+        "eth2_seededRandom",
+        &[ValueType::I32; 2],
+        None,
+        SEEDEDRANDOM_FUNC_INDEX,
+    ),
+    ("eth2_abort", &[ValueType::I32], None, ABORT_FUNC_INDEX),
+    (
+        "eth2_envCount",
+        &[],
+        Some(ValueType::I32),
+        ENVCOUNT_FUNC_INDEX,
+    ),
+    ("eth2_assert", &[ValueType::I32; 3], None, ASSERT_FUNC_INDEX),
+    (
+        "eth2_readU64BE",
+        &[ValueType::I32],
+        Some(ValueType::I64),
+        READU64BE_FUNC_INDEX,
+    ),
+    (
+        "eth2_writeU64BE",
+        &[ValueType::I32, ValueType::I64],
+        None,
+        WRITEU64BE_FUNC_INDEX,
+    ),
+    (
+        "eth2_verifyMerkleProof",
+        &[ValueType::I32; 5],
+        Some(ValueType::I32),
+        VERIFYMERKLEPROOF_FUNC_INDEX,
+    ),
+    (
+        "eth2_codeSize",
+        &[],
+        Some(ValueType::I32),
+        CODESIZE_FUNC_INDEX,
+    ),
+    (
+        "eth2_codeCopy",
+        &[ValueType::I32; 3],
+        None,
+        CODECOPY_FUNC_INDEX,
+    ),
+    (
+        "eth2_emitLog",
+        &[ValueType::I32; 4],
+        None,
+        EMITLOG_FUNC_INDEX,
+    ),
+    (
+        "eth2_parentRoot",
+        &[ValueType::I32],
+        None,
+        PARENTROOT_FUNC_INDEX,
+    ),
+    (
+        "eth2_memcmp",
+        &[ValueType::I32; 3],
+        Some(ValueType::I32),
+        MEMCMP_FUNC_INDEX,
+    ),
+    (
+        "eth2_depositStagedLen",
+        &[],
+        Some(ValueType::I32),
+        DEPOSITSTAGEDLEN_FUNC_INDEX,
+    ),
+    (
+        "eth2_envExists",
+        &[ValueType::I32],
+        Some(ValueType::I32),
+        ENVEXISTS_FUNC_INDEX,
+    ),
+    (
+        "eth2_savePostStateFrom",
+        &[ValueType::I32; 2],
+        None,
+        SAVEPOSTSTATEFROM_FUNC_INDEX,
+    ),
+    (
+        "eth2_blockDataRoot",
+        &[ValueType::I32],
+        None,
+        BLOCKDATAROOT_FUNC_INDEX,
+    ),
+    (
+        "eth2_storageSlotCount",
+        &[],
+        Some(ValueType::I32),
+        STORAGESLOTCOUNT_FUNC_INDEX,
+    ),
+    (
+        "eth2_gasLeft",
+        &[],
+        Some(ValueType::I64),
+        GASLEFT_FUNC_INDEX,
+    ),
+    (
+        "eth2_hexEncode",
+        &[ValueType::I32; 4],
+        Some(ValueType::I32),
+        HEXENCODE_FUNC_INDEX,
+    ),
+    ("gas", &[ValueType::I32], None, GAS_FUNC_INDEX),
+];
+
+/// Resolves the built-in host functions. It holds no state, so every execution links against
+/// the same one.
+struct RuntimeModuleImportResolver;
+
+/// Scripts may import the host functions from either namespace, the metering pass always
+/// imports `gas` from `env`. Resolving the namespaces here rather than registering the
+/// resolver in an `ImportsBuilder` saves building its map for every execution.
+const IMPORT_NAMESPACES: [&str; 2] = ["env", "eth2"];
+
+impl RuntimeModuleImportResolver {
+    fn namespace(&self, module_name: &str) -> Result<&Self, InterpreterError> {
+        if IMPORT_NAMESPACES.contains(&module_name) {
+            Ok(self)
+        } else {
+            Err(InterpreterError::Instantiation(format!(
+                "Module {} not found",
+                module_name
+            )))
+        }
+    }
+}
+
+impl ImportResolver for RuntimeModuleImportResolver {
+    fn resolve_func(
+        &self,
+        module_name: &str,
+        field_name: &str,
+        signature: &Signature,
+    ) -> Result<FuncRef, InterpreterError> {
+        ModuleImportResolver::resolve_func(self.namespace(module_name)?, field_name, signature)
+    }
+
+    fn resolve_global(
+        &self,
+        module_name: &str,
+        field_name: &str,
+        descriptor: &GlobalDescriptor,
+    ) -> Result<GlobalRef, InterpreterError> {
+        ModuleImportResolver::resolve_global(self.namespace(module_name)?, field_name, descriptor)
+    }
+
+    fn resolve_memory(
+        &self,
+        module_name: &str,
+        field_name: &str,
+        descriptor: &MemoryDescriptor,
+    ) -> Result<MemoryRef, InterpreterError> {
+        ModuleImportResolver::resolve_memory(self.namespace(module_name)?, field_name, descriptor)
+    }
+
+    fn resolve_table(
+        &self,
+        module_name: &str,
+        field_name: &str,
+        descriptor: &TableDescriptor,
+    ) -> Result<TableRef, InterpreterError> {
+        ModuleImportResolver::resolve_table(self.namespace(module_name)?, field_name, descriptor)
+    }
+}
+
+impl ModuleImportResolver for RuntimeModuleImportResolver {
+    fn resolve_func(
+        &self,
+        field_name: &str,
+        signature: &Signature,
+    ) -> Result<FuncRef, InterpreterError> {
+        let variants = || {
+            BUILTIN_HOST_FNS
+                .iter()
+                .filter(|(name, ..)| *name == field_name)
+        };
+        let (_, params, result, index) = variants()
+            .find(|(_, _, result, _)| *result == signature.return_type())
+            .or_else(|| variants().next())
+            .ok_or_else(|| {
+                InterpreterError::Function(format!(
+                    "host module doesn't export function with name {}",
+                    field_name
+                ))
+            })?;
+        Ok(FuncInstance::alloc_host(
+            Signature::new(*params, *result),
+            *index,
+        ))
+    }
+}
+
+/// Resolves the functions registered in `HostFunctions` on top of the built-in ones. Only
+/// used for configs which register any, the others link against `RuntimeModuleImportResolver`.
+struct HostFunctionsResolver<'a> {
+    host_functions: &'a HostFunctions,
+}
+
+impl<'a> ImportResolver for HostFunctionsResolver<'a> {
+    fn resolve_func(
+        &self,
+        module_name: &str,
+        field_name: &str,
+        signature: &Signature,
+    ) -> Result<FuncRef, InterpreterError> {
+        RuntimeModuleImportResolver.namespace(module_name)?;
+        ModuleImportResolver::resolve_func(self, field_name, signature)
+    }
+
+    fn resolve_global(
+        &self,
+        module_name: &str,
+        field_name: &str,
+        descriptor: &GlobalDescriptor,
+    ) -> Result<GlobalRef, InterpreterError> {
+        ImportResolver::resolve_global(
+            &RuntimeModuleImportResolver,
+            module_name,
+            field_name,
+            descriptor,
+        )
+    }
+
+    fn resolve_memory(
+        &self,
+        module_name: &str,
+        field_name: &str,
+        descriptor: &MemoryDescriptor,
+    ) -> Result<MemoryRef, InterpreterError> {
+        ImportResolver::resolve_memory(
+            &RuntimeModuleImportResolver,
+            module_name,
+            field_name,
+            descriptor,
+        )
+    }
+
+    fn resolve_table(
+        &self,
+        module_name: &str,
+        field_name: &str,
+        descriptor: &TableDescriptor,
+    ) -> Result<TableRef, InterpreterError> {
+        ImportResolver::resolve_table(
+            &RuntimeModuleImportResolver,
+            module_name,
+            field_name,
+            descriptor,
+        )
+    }
+}
+
+impl<'a> ModuleImportResolver for HostFunctionsResolver<'a> {
+    fn resolve_func(
+        &self,
+        field_name: &str,
+        signature: &Signature,
+    ) -> Result<FuncRef, InterpreterError> {
+        ModuleImportResolver::resolve_func(&RuntimeModuleImportResolver, field_name, signature)
+            .or_else(|error| match self.host_functions.position(field_name) {
+                Some(position) => Ok(FuncInstance::alloc_host(
+                    self.host_functions.functions[position].1.clone(),
+                    CUSTOM_FUNC_INDEX_BASE + position,
+                )),
+                None => Err(error),
+            })
+    }
+}
+
+/// Names the WASM proposal a decoding error stems from, if it is one outside of the supported
+/// MVP feature set. The decoder is built without proposal support, so their opcodes and value
+/// types surface as unknown.
+fn unsupported_feature(error: &parity_wasm::elements::Error) -> Option<&'static str> {
+    use parity_wasm::elements::Error;
+    match *error {
+        Error::UnknownOpcode(0xfd) | Error::UnknownValueType(-0x05) => Some("simd"),
+        Error::UnknownOpcode(0xfc) | Error::InvalidSegmentFlags(_) => Some("bulk-memory"),
+        Error::UnknownOpcode(0xfe) => Some("threads"),
+        Error::UnknownOpcode(0xc0..=0xc4) => Some("sign-extension"),
+        Error::UnknownOpcode(0x1c)
+        | Error::UnknownOpcode(0x25..=0x26)
+        | Error::UnknownOpcode(0xd0..=0xd2)
+        | Error::UnknownValueType(-0x11..=-0x10) => Some("reference-types"),
+        _ => None,
+    }
+}
+
+/// Loads the module with a call to the `gas` host function injected at the start of every block.
+/// Also returns the index of the mutable global exported as `gas` by ewasm metered modules, and
+/// the one `inject_call_depth_limit` counts calls in.
+fn wasm_load_from_blob(
+    buf: &[u8],
+    config: &ExecutionConfig,
+) -> Result<(Module, Option<u32>, Option<u32>), ExecError> {
+    if buf.is_empty() {
+        return Err(ExecError::EmptyScript);
+    }
+    if config.reject_trailing_bytes {
+        check_trailing_bytes(buf)?;
+    }
+    let mut module = parity_wasm::deserialize_buffer::<parity_wasm::elements::Module>(buf)
+        .map_err(|e| match unsupported_feature(&e) {
+            Some(feature) => ExecError::UnsupportedFeature(feature.to_string()),
+            None => ExecError::InvalidModule(e.to_string()),
+        })?;
+    if memory_count(&module) > 1 {
+        return Err(ExecError::MultipleMemories);
+    }
+    // Checked before the metering pass adds its own `env` import.
+    if config.strict_import_namespace {
+        check_import_namespace(&module)?;
+    }
+    let gas_global = take_gas_global_export(&mut module);
+    let mut module =
+        pwasm_utils::inject_gas_counter(module, &pwasm_utils::rules::Set::default())
+            .map_err(|_| ExecError::InvalidModule("failed to inject gas counter".to_string()))?;
+    // Injected after the metering, so the counting is not charged for.
+    let call_depth_global = inject_call_depth_limit(&mut module, config.call_stack_limit);
+    let module = Module::from_parity_wasm_module(module)
+        .map_err(|e| ExecError::InvalidModule(e.to_string()))?;
+    Ok((module, gas_global, call_depth_global))
+}
+
+/// Counts how deeply calls between the functions of the module nest in a new mutable global,
+/// trapping once they nest deeper than `limit`, and returns the index of the global. wasmi
+/// reports an exhausted call stack the same way as an exhausted value stack, so the call depth
+/// is bounded here instead, which lets `execute` tell the two apart. Modules without such calls
+/// are left as they are.
+fn inject_call_depth_limit(
+    module: &mut parity_wasm::elements::Module,
+    limit: usize,
+) -> Option<u32> {
+    use parity_wasm::elements::{
+        BlockType, GlobalEntry, GlobalSection, GlobalType, ImportCountType, InitExpr, Instruction,
+        Section, ValueType,
+    };
+
+    let imported_functions = module.import_count(ImportCountType::Function) as u32;
+    let is_counted = |instruction: &Instruction| match instruction {
+        Instruction::Call(function) => *function >= imported_functions,
+        Instruction::CallIndirect(..) => true,
+        _ => false,
+    };
+    let has_calls = module.code_section().is_some_and(|code| {
+        code.bodies()
+            .iter()
+            .any(|body| body.code().elements().iter().any(is_counted))
+    });
+    if !has_calls {
+        return None;
+    }
+
+    let index = (module.import_count(ImportCountType::Global)
+        + module
+            .global_section()
+            .map_or(0, |globals| globals.entries().len())) as u32;
+    if module.global_section().is_none() {
+        module
+            .insert_section(Section::Global(GlobalSection::with_entries(Vec::new())))
+            .expect("a module has at most one global section");
+    }
+    module
+        .global_section_mut()
+        .expect("inserted above")
+        .entries_mut()
+        .push(GlobalEntry::new(
+            GlobalType::new(ValueType::I32, true),
+            InitExpr::new(vec![Instruction::I32Const(0), Instruction::End]),
+        ));
+
+    // Compared as unsigned, so a limit beyond the range of an i32 never trips.
+    let limit = u32::try_from(limit).unwrap_or(u32::MAX) as i32;
+    let bodies = module.code_section_mut().expect("has calls").bodies_mut();
+    for body in bodies {
+        let code = body.code_mut().elements_mut();
+        let mut instrumented = Vec::with_capacity(code.len());
+        for instruction in code.drain(..) {
+            if !is_counted(&instruction) {
+                instrumented.push(instruction);
+                continue;
+            }
+            instrumented.extend_from_slice(&[
+                Instruction::GetGlobal(index),
+                Instruction::I32Const(1),
+                Instruction::I32Add,
+                Instruction::SetGlobal(index),
+                Instruction::GetGlobal(index),
+                Instruction::I32Const(limit),
+                Instruction::I32GtU,
+                Instruction::If(BlockType::NoResult),
+                Instruction::Unreachable,
+                Instruction::End,
+            ]);
+            instrumented.push(instruction);
+            instrumented.extend_from_slice(&[
+                Instruction::GetGlobal(index),
+                Instruction::I32Const(1),
+                Instruction::I32Sub,
+                Instruction::SetGlobal(index),
+            ]);
+        }
+        *code = instrumented;
+    }
+    Some(index)
+}
+
+/// Number of memories imported or defined by the module. The host functions all work on a
+/// single memory.
+fn memory_count(module: &parity_wasm::elements::Module) -> usize {
+    use parity_wasm::elements::External;
+
+    let imported = module.import_section().map_or(0, |imports| {
+        imports
+            .entries()
+            .iter()
+            .filter(|entry| matches!(entry.external(), External::Memory(_)))
+            .count()
+    });
+    imported
+        + module
+            .memory_section()
+            .map_or(0, |memories| memories.entries().len())
+}
+
+/// Walks the section headers following the module header, failing at the first one which
+/// has an unknown id or does not fit in the buffer. Codes without a module header are left
+/// for the decoder to reject.
+fn check_trailing_bytes(buf: &[u8]) -> Result<(), ExecError> {
+    /// Reads an unsigned LEB128 u32 at `offset`, advancing past it.
+    fn read_var_u32(buf: &[u8], offset: &mut usize) -> Option<u32> {
+        let mut value = 0u32;
+        for shift in (0..35).step_by(7) {
+            let byte = *buf.get(*offset)?;
+            *offset += 1;
+            value |= u32::from(byte & 0x7f).checked_shl(shift)?;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+    /// The highest section id, of the data count section.
+    const MAX_SECTION_ID: u8 = 12;
+
+    if buf.len() < 8 || buf[..4] != *b"\0asm" {
+        return Ok(());
+    }
+    let mut offset = 8;
+    while offset < buf.len() {
+        let start = offset;
+        let id = buf[offset];
+        offset += 1;
+        match read_var_u32(buf, &mut offset) {
+            Some(size) if id <= MAX_SECTION_ID && size as usize <= buf.len() - offset => {
+                offset += size as usize;
+            }
+            _ => return Err(ExecError::TrailingBytes(start)),
+        }
+    }
+    Ok(())
+}
+
+/// Requires every function import to come from the `eth2` namespace.
+fn check_import_namespace(module: &parity_wasm::elements::Module) -> Result<(), ExecError> {
+    use parity_wasm::elements::External;
+
+    let entries = module
+        .import_section()
+        .map_or(&[][..], |imports| imports.entries());
+    match entries
+        .iter()
+        .find(|entry| matches!(entry.external(), External::Function(_)) && entry.module() != "eth2")
+    {
+        Some(entry) => Err(ExecError::UnexpectedImportModule(
+            entry.module().to_string(),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Removes a mutable global exported as `gas` and returns its index. wasmi only validates
+/// immutable global exports, so the global is read through the instance globals instead.
+fn take_gas_global_export(module: &mut parity_wasm::elements::Module) -> Option<u32> {
+    use parity_wasm::elements::{External, Internal};
+
+    let index = module.export_section().and_then(|exports| {
+        exports
+            .entries()
+            .iter()
+            .find_map(|entry| match entry.internal() {
+                Internal::Global(index) if entry.field() == "gas" => Some(*index),
+                _ => None,
+            })
+    })?;
+    let imported_globals: Vec<bool> = module
+        .import_section()
+        .map_or(&[][..], |imports| imports.entries())
+        .iter()
+        .filter_map(|entry| match entry.external() {
+            External::Global(global) => Some(global.is_mutable()),
+            _ => None,
+        })
+        .collect();
+    let defined_globals = module
+        .global_section()
+        .map_or(&[][..], |globals| globals.entries());
+    let is_mutable = match imported_globals.get(index as usize) {
+        Some(mutable) => *mutable,
+        None => defined_globals
+            .get(index as usize - imported_globals.len())
+            .is_some_and(|global| global.global_type().is_mutable()),
+    };
+    if !is_mutable {
+        return None;
+    }
+    module
+        .export_section_mut()
+        .expect("export found above")
+        .entries_mut()
+        .retain(|entry| entry.field() != "gas");
+    Some(index)
+}
+
+/// Basic facts about a module, reported by `validate_wasm`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WasmInfo {
+    /// Functions defined in the module, not counting imported ones.
+    pub functions: usize,
+    pub imports: usize,
+    pub exports: usize,
+    /// Initial and maximum pages of the defined or imported memory.
+    pub memory: Option<(u32, Option<u32>)>,
+}
+
+/// Converts the module back to the text format, for debugging scripts.
+pub fn disassemble(code: &[u8]) -> Result<String, ScoutError> {
+    wasmprinter::print_bytes(code).map_err(|e| ScoutError::InvalidWasm(e.to_string()))
+}
+
+/// Decodes and validates the module without instantiating it.
+pub fn validate_wasm(code: &[u8]) -> Result<WasmInfo, ScoutError> {
+    use parity_wasm::elements::External;
+
+    let module =
+        parity_wasm::deserialize_buffer::<parity_wasm::elements::Module>(code).map_err(|e| {
+            match unsupported_feature(&e) {
+                Some(feature) => {
+                    ScoutError::InvalidWasm(format!("unsupported feature: {}", feature))
+                }
+                None => ScoutError::InvalidWasm(e.to_string()),
+            }
+        })?;
+
+    if memory_count(&module) > 1 {
+        return Err(ScoutError::InvalidWasm(
+            ExecError::MultipleMemories.to_string(),
+        ));
+    }
+    let functions = module.function_section().map_or(0, |s| s.entries().len());
+    let bodies = module.code_section().map_or(0, |s| s.bodies().len());
+    if functions != bodies {
+        return Err(ScoutError::InvalidWasm(format!(
+            "{} functions declared but {} bodies present",
+            functions, bodies
+        )));
+    }
+    let imports = module.import_section().map_or(&[][..], |s| s.entries());
+    let imported_memory = imports.iter().find_map(|entry| match entry.external() {
+        External::Memory(memory) => Some(memory.limits()),
+        _ => None,
+    });
+    let memory = module
+        .memory_section()
+        .and_then(|s| s.entries().first())
+        .map(|memory| memory.limits())
+        .or(imported_memory)
+        .map(|limits| (limits.initial(), limits.maximum()));
+    let info = WasmInfo {
+        functions,
+        imports: imports.len(),
+        exports: module.export_section().map_or(0, |s| s.entries().len()),
+        memory,
+    };
+
+    Module::from_parity_wasm_module(module).map_err(|e| ScoutError::InvalidWasm(e.to_string()))?;
+    Ok(info)
+}
+
+const ZERO_HASH: Bytes32 = Bytes32 { bytes: [0u8; 32] };
+/// Size of a WASM memory page, for every conversion from pages to bytes.
+const WASM_PAGE_SIZE: usize = 65536;
+const SECONDS_PER_SLOT: u64 = 6;
+const SLOTS_PER_EPOCH: u64 = 64;
+const LATEST_STATE_ROOTS_LENGTH: usize = 64;
+
+const DEFAULT_MAX_DEPOSITS: usize = 1024;
+/// Most deposits a valid block may produce, the `MAX_DEPOSITS` of the spec.
+const MAX_DEPOSITS_PER_BLOCK: usize = 16;
+
+/// Size of a serialized `DepositData`: pubkey, withdrawal credentials, amount and signature.
+const DEPOSIT_DATA_SIZE: usize = 48 + 32 + 8 + 96;
+
+/// These are Phase 0 structures.
+/// https://github.com/ethereum/eth2.0-specs/blob/dev/specs/core/0_beacon-chain.md
+#[derive(Clone, Debug)]
+pub struct Deposit {
+    pubkey: [u8; 48],
+    withdrawal_credentials: Bytes32,
+    amount: u64,
+    signature: [u8; 96],
+}
+
+impl Deposit {
+    /// Decodes a deposit from its fixed-size SSZ serialization.
+    fn from_bytes(bytes: &[u8]) -> Option<Deposit> {
+        if bytes.len() != DEPOSIT_DATA_SIZE {
+            return None;
+        }
+        let mut deposit = Deposit {
+            pubkey: [0u8; 48],
+            withdrawal_credentials: Bytes32::default(),
+            amount: 0,
+            signature: [0u8; 96],
+        };
+        deposit.pubkey.copy_from_slice(&bytes[0..48]);
+        deposit
+            .withdrawal_credentials
+            .bytes
+            .copy_from_slice(&bytes[48..80]);
+        let mut amount = [0u8; 8];
+        amount.copy_from_slice(&bytes[80..88]);
+        deposit.amount = u64::from_le_bytes(amount);
+        deposit.signature.copy_from_slice(&bytes[88..184]);
+        Some(deposit)
+    }
+}
+
+/// These are Phase 2 Proposal 2 structures.
+
+#[derive(Default, Clone, Debug)]
+pub struct ExecutionScript {
+    code: Vec<u8>,
+}
+
+impl ExecutionScript {
+    /// Loads a script from a binary module, or from the text format for `.wat` files.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<ExecutionScript> {
+        let path = path.as_ref();
+        let code = if path.extension().is_some_and(|ext| ext == "wat") {
+            wat::parse_file(path)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?
+        } else {
+            std::fs::read(path)?
+        };
+        Ok(ExecutionScript { code })
+    }
+}
+
+#[derive(Default, Clone, Debug)]
+pub struct BeaconState {
+    execution_scripts: Vec<ExecutionScript>,
+}
+
+impl BeaconState {
+    /// Saves the execution scripts to `path`, see `write_to` for the format.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<BeaconState> {
+        BeaconState::read_from(&mut BufReader::new(File::open(path)?))
+    }
+
+    /// Loads every `.wasm` and `.wat` file in the directory as a script, with the environments
+    /// numbered in file name order. Other entries are skipped.
+    pub fn from_dir<P: AsRef<Path>>(path: P) -> io::Result<BeaconState> {
+        let mut paths = std::fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        paths.sort();
+
+        let mut execution_scripts = Vec::new();
+        for path in paths {
+            let is_script = path
+                .extension()
+                .is_some_and(|ext| ext == "wasm" || ext == "wat");
+            if !is_script || !path.is_file() {
+                warn!(path = %path.display(), "skipping entry which is not a script");
+                continue;
+            }
+            execution_scripts.push(ExecutionScript::from_file(&path)?);
+        }
+        Ok(BeaconState { execution_scripts })
+    }
+
+    /// Merkle root over the SHA-256 of each script's code, in environment order, which
+    /// identifies the set of execution environments.
+    // TODO: replace with the SSZ hash tree root
+    pub fn root(&self) -> Bytes32 {
+        let leaves: Vec<Bytes32> = self
+            .execution_scripts
+            .iter()
+            .map(|script| Sha256Hasher.hash(&script.code))
+            .collect();
+        Sha256Hasher.hash_tree_root(&leaves)
+    }
+
+    /// Instantiates each script against the host functions without running it, catching
+    /// imports which do not resolve and missing memory exports before any block is applied.
+    pub fn preflight(&self) -> Vec<(usize, Result<(), ExecError>)> {
+        let config = ExecutionConfig::default();
+        self.execution_scripts
+            .iter()
+            .enumerate()
+            .map(|(index, script)| {
+                let result = wasm_load_from_blob(&script.code, &config)
+                    .and_then(|(module, ..)| instantiate(&module, &config).map(|_| ()));
+                (index, result)
+            })
+            .collect()
+    }
+
+    /// Writes the script count followed by each script's code, all prefixed by their length
+    /// as a little-endian u32.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&(self.execution_scripts.len() as u32).to_le_bytes())?;
+        for script in &self.execution_scripts {
+            writer.write_all(&(script.code.len() as u32).to_le_bytes())?;
+            writer.write_all(&script.code)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<BeaconState> {
+        fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+            let mut buf = [0u8; 4];
+            reader.read_exact(&mut buf)?;
+            Ok(u32::from_le_bytes(buf))
+        }
+
+        let count = read_u32(reader)?;
+        let mut execution_scripts = Vec::new();
+        for _ in 0..count {
+            let length = read_u32(reader)?;
+            // Read through `take` so a bogus length prefix can't trigger a huge allocation.
+            let mut code = Vec::new();
+            reader.take(u64::from(length)).read_to_end(&mut code)?;
+            if code.len() != length as usize {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "truncated execution script",
+                ));
+            }
+            execution_scripts.push(ExecutionScript { code });
+        }
+        Ok(BeaconState { execution_scripts })
+    }
+}
+
+/// Shards are Phase 1 structures.
+/// https://github.com/ethereum/eth2.0-specs/blob/dev/specs/core/1_shard-data-chains.md
+
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+pub struct ShardBlockHeader {
+    pub slot: u64,
+    /// `hash_tree_root` of the previous block's header, zero for the first block.
+    pub parent_root: Bytes32,
+    /// Root of the shard state once the block was applied, before moving to the next slot.
+    pub state_root: Bytes32,
+    /// `ShardBlock::root` of the block, committing to its environment and data.
+    pub body_root: Bytes32,
+}
+
+impl ShardBlockHeader {
+    /// Merkle root over the fields in order, with the slot as a little-endian leaf.
+    // TODO: replace with the SSZ hash tree root
+    pub fn hash_tree_root(&self) -> Bytes32 {
+        let mut slot = [0u8; 32];
+        slot[..8].copy_from_slice(&self.slot.to_le_bytes());
+        Sha256Hasher.hash_tree_root(&[
+            Bytes32::from(slot),
+            self.parent_root,
+            self.state_root,
+            self.body_root,
+        ])
+    }
+}
+
+#[derive(Default, Clone, Debug)]
+pub struct ShardBlockBody {
+    data: Bytes,
+}
+
+impl ShardBlockBody {
+    /// Encodes the body as a little-endian u32 length prefix followed by the data.
+    pub fn to_ssz(&self) -> Vec<u8> {
+        self.data.to_ssz()
+    }
+
+    /// Decodes a body encoded by `to_ssz`. The length prefix must match the rest of the buffer
+    /// exactly.
+    pub fn from_ssz(encoded: &[u8]) -> Result<ShardBlockBody, ScoutError> {
+        Ok(ShardBlockBody {
+            data: Bytes::from_ssz(encoded)?,
+        })
+    }
+}
+
+#[derive(Default, Clone, Debug)]
+pub struct ShardBlock {
+    env: u64, // This is added by Phase 2 Proposal 2
+    data: ShardBlockBody,
+    // TODO: add missing fields
+}
+
+impl ShardBlock {
+    /// Encodes the block as its little-endian u64 env followed by the encoded body.
+    pub fn to_ssz(&self) -> Vec<u8> {
+        let mut encoded = self.env.to_le_bytes().to_vec();
+        encoded.extend_from_slice(&self.data.to_ssz());
+        encoded
+    }
+
+    /// SHA-256 over the encoded block.
+    // TODO: replace with the SSZ hash tree root
+    pub fn root(&self) -> Bytes32 {
+        Bytes32::from(<[u8; 32]>::from(Sha256::digest(self.to_ssz())))
+    }
+}
+
+/// Decodes a stream of blocks encoded by `ShardBlock::to_ssz`, one block at a time.
+pub struct BlockReader<R> {
+    reader: R,
+    done: bool,
+}
+
+impl<R: Read> BlockReader<R> {
+    pub fn new(reader: R) -> BlockReader<R> {
+        BlockReader {
+            reader,
+            done: false,
+        }
+    }
+
+    /// Fills `buf`, returning `false` if the stream ended before its first byte.
+    fn read_exact_or_end(&mut self, buf: &mut [u8]) -> Result<bool, ScoutError> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            match self.reader.read(&mut buf[filled..]) {
+                Ok(0) if filled == 0 => return Ok(false),
+                Ok(0) => return Err(ScoutError::MalformedSsz("truncated block".to_string())),
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(ScoutError::Io(e.to_string())),
+            }
+        }
+        Ok(true)
+    }
+
+    fn read_block(&mut self) -> Result<Option<ShardBlock>, ScoutError> {
+        let mut env = [0u8; 8];
+        if !self.read_exact_or_end(&mut env)? {
+            return Ok(None);
+        }
+        let mut prefix = [0u8; 4];
+        if !self.read_exact_or_end(&mut prefix)? {
+            return Err(ScoutError::MalformedSsz("truncated block".to_string()));
+        }
+        let length = u32::from_le_bytes(prefix);
+        // Read through `take` so a bogus length prefix can't trigger a huge allocation.
+        let mut data = Vec::new();
+        (&mut self.reader)
+            .take(u64::from(length))
+            .read_to_end(&mut data)
+            .map_err(|e| ScoutError::Io(e.to_string()))?;
+        if data.len() != length as usize {
+            return Err(ScoutError::MalformedSsz(format!(
+                "length prefix declares {} bytes, {} present",
+                length,
+                data.len()
+            )));
+        }
+        Ok(Some(ShardBlock {
+            env: u64::from_le_bytes(env),
+            data: ShardBlockBody { data: data.into() },
+        }))
+    }
+}
+
+impl<R: Read> Iterator for BlockReader<R> {
+    type Item = Result<ShardBlock, ScoutError>;
+
+    /// Yields blocks until the end of the stream, stopping after the first error.
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let block = self.read_block().transpose();
+        self.done = !matches!(block, Some(Ok(_)));
+        block
+    }
+}
+
+/// Storage of the environment state roots of a shard.
+pub trait EnvStateStore: fmt::Debug {
+    /// The state root of the environment, zero for one which was never set.
+    fn get(&self, env: usize) -> Bytes32;
+    /// Sets the state root of the environment, adding zero roots for any environments
+    /// before it which were not set yet.
+    fn set(&mut self, env: usize, root: Bytes32);
+    /// Number of environments, one past the highest which was set.
+    fn len(&self) -> usize;
+    /// Drops the environments from `len` on, undoing `set` calls which added them.
+    fn truncate(&mut self, len: usize);
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// The default store, holding every root in memory.
+impl EnvStateStore for Vec<Bytes32> {
+    fn get(&self, env: usize) -> Bytes32 {
+        self.as_slice().get(env).copied().unwrap_or(ZERO_HASH)
+    }
+
+    fn set(&mut self, env: usize, root: Bytes32) {
+        if env >= self.len() {
+            self.resize(env + 1, ZERO_HASH);
+        }
+        self[env] = root;
+    }
+
+    fn len(&self) -> usize {
+        self.as_slice().len()
+    }
+
+    fn truncate(&mut self, len: usize) {
+        Vec::truncate(self, len)
+    }
+}
+
+#[derive(Default, Clone, Debug)]
+pub struct ShardState<S = Vec<Bytes32>> {
+    exec_env_states: S,
+    slot: u64,
+    /// Header of the last block applied to the shard, none before the first block.
+    parent_block: Option<ShardBlockHeader>,
+    /// State roots at the end of the most recent slots, oldest first, holding at most
+    /// `LATEST_STATE_ROOTS_LENGTH` entries.
+    latest_state_roots: Vec<Bytes32>,
+}
+
+impl<S: EnvStateStore> ShardState<S> {
+    /// SHA-256 over the slot and the environment states.
+    // TODO: replace with the SSZ hash tree root
+    pub fn state_root(&self) -> Bytes32 {
+        self.state_root_with(&Sha256Hasher)
+    }
+
+    /// Like `state_root`, with the given hash function.
+    pub fn state_root_with<H: Hasher>(&self, hasher: &H) -> Bytes32 {
+        let mut input = self.slot.to_le_bytes().to_vec();
+        for env in 0..self.exec_env_states.len() {
+            input.extend_from_slice(&self.exec_env_states.get(env).bytes);
+        }
+        hasher.hash(&input)
+    }
+
+    /// Root of the last block's header, the zero hash before the first block.
+    pub fn parent_root(&self) -> Bytes32 {
+        self.parent_block
+            .as_ref()
+            .map(ShardBlockHeader::hash_tree_root)
+            .unwrap_or_default()
+    }
+
+    /// Records the state root of the current slot and moves to the next one, returning the
+    /// oldest root if it had to make room for it.
+    fn advance_slot(&mut self) -> Option<Bytes32> {
+        let evicted = if self.latest_state_roots.len() == LATEST_STATE_ROOTS_LENGTH {
+            Some(self.latest_state_roots.remove(0))
+        } else {
+            None
+        };
+        let root = self.state_root();
+        self.latest_state_roots.push(root);
+        self.slot += 1;
+        evicted
+    }
+}
+
+/// A write to the shard state recorded in a `ShardStateJournal`, with what it overwrote.
+#[derive(Clone, Debug)]
+enum JournalEntry {
+    EnvState {
+        env: usize,
+        previous: Bytes32,
+        previous_len: usize,
+    },
+    ParentBlock(Option<ShardBlockHeader>),
+    /// The state moved to the next slot, evicting the given root from the recent ones.
+    AdvanceSlot(Option<Bytes32>),
+}
+
+/// The writes `process_shard_block_journaled` made to a shard state, which `undo` reverts
+/// exactly. Unlike a `ShardStateSnapshot` it only keeps the entries which were written, so
+/// its cost does not grow with the environment store. Post-states and deposits stay in the
+/// runtime until an execution succeeds, so only the writes of applied blocks are recorded.
+#[derive(Default, Clone, Debug)]
+pub struct ShardStateJournal {
+    entries: Vec<JournalEntry>,
+}
+
+impl ShardStateJournal {
+    /// Reverts the recorded writes, newest first, and clears the journal.
+    pub fn undo<S: EnvStateStore>(&mut self, state: &mut ShardState<S>) {
+        while let Some(entry) = self.entries.pop() {
+            match entry {
+                JournalEntry::EnvState {
+                    env,
+                    previous,
+                    previous_len,
+                } => {
+                    state.exec_env_states.truncate(previous_len);
+                    if env < previous_len {
+                        state.exec_env_states.set(env, previous);
+                    }
+                }
+                JournalEntry::ParentBlock(previous) => state.parent_block = previous,
+                JournalEntry::AdvanceSlot(evicted) => {
+                    state.slot -= 1;
+                    state.latest_state_roots.pop();
+                    if let Some(root) = evicted {
+                        state.latest_state_roots.insert(0, root);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A copy of a `ShardState` taken by `ShardState::snapshot`, to roll back to after trying
+/// blocks which should not be kept.
+#[derive(Clone, Debug)]
+pub struct ShardStateSnapshot<S = Vec<Bytes32>> {
+    exec_env_states: S,
+    slot: u64,
+    parent_block: Option<ShardBlockHeader>,
+    latest_state_roots: Vec<Bytes32>,
+}
+
+impl<S: EnvStateStore + Clone> ShardState<S> {
+    /// Captures the environment states and the slot, along with the parent block and the
+    /// recent state roots which follow from them.
+    pub fn snapshot(&self) -> ShardStateSnapshot<S> {
+        ShardStateSnapshot {
+            exec_env_states: self.exec_env_states.clone(),
+            slot: self.slot,
+            parent_block: self.parent_block.clone(),
+            latest_state_roots: self.latest_state_roots.clone(),
+        }
+    }
+
+    /// Returns to the state the snapshot was taken of, discarding every block applied since.
+    pub fn restore(&mut self, snapshot: ShardStateSnapshot<S>) {
+        self.exec_env_states = snapshot.exec_env_states;
+        self.slot = snapshot.slot;
+        self.parent_block = snapshot.parent_block;
+        self.latest_state_roots = snapshot.latest_state_roots;
+    }
+}
+
+/// The changes a block made to the shard state.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StateDiff {
+    /// Environments whose state root changed, with their old and new roots. Environments
+    /// which did not exist before are compared against the zero hash.
+    pub changed_envs: Vec<(usize, Bytes32, Bytes32)>,
+    pub slot: (u64, u64),
+}
+
+impl StateDiff {
+    pub fn between<S: EnvStateStore>(pre: &ShardState<S>, post: &ShardState<S>) -> StateDiff {
+        let changed_envs = (0..post.exec_env_states.len())
+            .filter_map(|env| {
+                let old = pre.exec_env_states.get(env);
+                let new = post.exec_env_states.get(env);
+                if old != new {
+                    Some((env, old, new))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        StateDiff {
+            changed_envs,
+            slot: (pre.slot, post.slot),
+        }
+    }
+}
+
+impl fmt::Display for StateDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.slot.0 != self.slot.1 {
+            writeln!(f, "slot: {} -> {}", self.slot.0, self.slot.1)?;
+        }
+        if self.changed_envs.is_empty() {
+            writeln!(f, "no environment changed")?;
+        }
+        for (env, old, new) in &self.changed_envs {
+            writeln!(f, "env {}: {} -> {}", env, old, new)?;
+        }
+        Ok(())
+    }
+}
+
+/// The interpreter error behind an `ExecError`, captured under `capture_diagnostics`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The kind of the trap, if execution trapped.
+    pub trap_kind: Option<String>,
+    /// The full interpreter error, including any host error it carries.
+    pub detail: String,
+}
+
+impl Diagnostic {
+    fn new(e: &InterpreterError) -> Diagnostic {
+        Diagnostic {
+            trap_kind: match e {
+                InterpreterError::Trap(trap) => Some(format!("{:?}", trap.kind())),
+                _ => None,
+            },
+            detail: format!("{:?}", e),
+        }
+    }
+}
+
+/// Errors which can occur while executing an execution script.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ExecError {
+    /// The code could not be decoded as a WASM module.
+    InvalidModule(String),
+    /// The module uses a WASM proposal outside of the supported feature set.
+    UnsupportedFeature(String),
+    /// The module could not be instantiated, e.g. because of unresolved imports.
+    Instantiation(String),
+    /// The module is missing a required export.
+    MissingExport(String),
+    /// Execution of the script trapped.
+    Trap(String),
+    /// Execution was cancelled through the cancellation flag.
+    Cancelled,
+    /// A host function accessed memory outside of the script memory.
+    MemoryAccessOutOfBounds,
+    /// The script misused the incremental deposit functions.
+    InvalidDeposit(String),
+    /// The script emitted more deposits than `max_deposits` allows.
+    DepositLimitExceeded,
+    /// The block produced the given number of deposits, more than `MAX_DEPOSITS_PER_BLOCK`.
+    TooManyDeposits(usize),
+    /// The script requested a range outside of the block body.
+    BlockDataOutOfBounds,
+    /// The script requested a block data range ending before it starts, given as start and end.
+    ReversedBlockDataRange(usize, usize),
+    /// The script requested a range outside of its own code.
+    CodeOutOfBounds,
+    /// The script did not read the whole block body under `reject_unconsumed_data`.
+    UnconsumedBlockData,
+    /// The script never called `eth2_savePostState` under `require_post_state`.
+    NoPostState,
+    /// `eth2_blockDataCopy` targeted an unaligned pointer under `require_aligned_copies`.
+    UnalignedCopy(u32),
+    /// A block or `eth2_callEnv` targeted an environment which does not exist.
+    UnknownEnvironment(u64),
+    /// Script calls nested deeper than `call_stack_limit`.
+    CallDepthExceeded,
+    /// The script exhausted the interpreter value stack, bounded by `value_stack_limit`.
+    StackOverflow,
+    /// `eth2_callEnv` invocations nested deeper than `max_call_env_depth`.
+    EnvCallDepthExceeded,
+    /// The script called `eth2_abort` with the given code.
+    Aborted(i32),
+    /// An `eth2_assert` condition was false, with its message.
+    AssertionFailed(String),
+    /// The module uses floating-point under `reject_float`.
+    FloatingPointForbidden,
+    /// The module has more than one memory, the multi-memory proposal is not supported.
+    MultipleMemories,
+    /// An `eth2_emitLog` call passed more than `MAX_LOG_TOPICS` topics.
+    TooManyLogTopics(u32),
+    /// The script made more host calls than `max_host_calls` allows.
+    HostCallLimitExceeded,
+    /// `eth2_savePostStateFrom` was given a length other than the 32 bytes of a state.
+    BadPostStateLength(u32),
+    /// The block signature was rejected by the `BlockSignatureVerifier`.
+    InvalidSignature,
+    /// An error with the diagnostic captured under `capture_diagnostics`.
+    Diagnosed(Box<ExecError>, Diagnostic),
+    /// The module imports a function from the given namespace under `strict_import_namespace`.
+    UnexpectedImportModule(String),
+    /// The script used more gas than `gas_limit` allows.
+    OutOfGas,
+    /// The module has a start function and `run_start` is not set.
+    UnexpectedStart,
+    /// The host could not allocate a memory.
+    MemoryAllocationFailed,
+    /// The code of the script is empty, and `skip_empty_scripts` is not set.
+    EmptyScript,
+    /// Bytes from the given offset on do not form a section, under `reject_trailing_bytes`.
+    TrailingBytes(usize),
+    /// The script saved a post-state more than once under `single_post_state`.
+    MultiplePostStates,
+}
+
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExecError::InvalidModule(msg) => write!(f, "invalid module: {}", msg),
+            ExecError::UnsupportedFeature(name) => write!(f, "unsupported wasm feature: {}", name),
+            ExecError::Instantiation(msg) => write!(f, "instantiation failed: {}", msg),
+            ExecError::MissingExport(name) => write!(f, "missing export: {}", name),
+            ExecError::Trap(msg) => write!(f, "execution trapped: {}", msg),
+            ExecError::Cancelled => write!(f, "execution cancelled"),
+            ExecError::MemoryAccessOutOfBounds => write!(f, "memory access out of bounds"),
+            ExecError::InvalidDeposit(msg) => write!(f, "invalid deposit: {}", msg),
+            ExecError::DepositLimitExceeded => write!(f, "deposit limit exceeded"),
+            ExecError::TooManyDeposits(count) => write!(f, "too many deposits: {}", count),
+            ExecError::BlockDataOutOfBounds => write!(f, "block data access out of bounds"),
+            ExecError::ReversedBlockDataRange(start, end) => {
+                write!(f, "reversed block data range: {}..{}", start, end)
+            }
+            ExecError::UnconsumedBlockData => write!(f, "block data was not fully consumed"),
+            ExecError::NoPostState => write!(f, "script did not save a post-state"),
+            ExecError::UnalignedCopy(ptr) => write!(f, "unaligned block data copy to {}", ptr),
+            ExecError::UnknownEnvironment(env) => {
+                write!(f, "unknown execution environment {}", env)
+            }
+            ExecError::CallDepthExceeded => write!(f, "call depth exceeded"),
+            ExecError::StackOverflow => write!(f, "stack overflow"),
+            ExecError::EnvCallDepthExceeded => write!(f, "environment call depth exceeded"),
+            ExecError::Aborted(code) => write!(f, "aborted with code {}", code),
+            ExecError::AssertionFailed(message) => write!(f, "assertion failed: {}", message),
+            ExecError::FloatingPointForbidden => write!(f, "floating-point is forbidden"),
+            ExecError::MultipleMemories => write!(f, "multiple memories are not supported"),
+            ExecError::TooManyLogTopics(count) => write!(f, "too many log topics: {}", count),
+            ExecError::HostCallLimitExceeded => write!(f, "host call limit exceeded"),
+            ExecError::BadPostStateLength(length) => {
+                write!(f, "bad post-state length: {}", length)
+            }
+            ExecError::InvalidSignature => write!(f, "invalid block signature"),
+            ExecError::Diagnosed(error, diagnostic) => {
+                write!(f, "{} ({})", error, diagnostic.detail)
+            }
+            ExecError::UnexpectedImportModule(name) => {
+                write!(f, "unexpected import module: {}", name)
+            }
+            ExecError::OutOfGas => write!(f, "out of gas"),
+            ExecError::UnexpectedStart => write!(f, "module has a start function"),
+            ExecError::MemoryAllocationFailed => write!(f, "memory allocation failed"),
+            ExecError::EmptyScript => write!(f, "empty script"),
+            ExecError::TrailingBytes(offset) => write!(f, "trailing bytes at offset {}", offset),
+            ExecError::MultiplePostStates => write!(f, "post-state saved more than once"),
+            ExecError::CodeOutOfBounds => write!(f, "code access out of bounds"),
+        }
+    }
+}
+
+impl std::error::Error for ExecError {}
+
+/// Lets host functions abort execution with an `ExecError`, which is recovered in `From<InterpreterError>`.
+impl HostError for ExecError {}
+
+impl From<InterpreterError> for ExecError {
+    fn from(e: InterpreterError) -> Self {
+        // Errors raised by the host functions are passed through as they are.
+        if let Some(host_error) = e
+            .as_host_error()
+            .and_then(|e| e.downcast_ref::<ExecError>())
+        {
+            return host_error.clone();
+        }
+        match e {
+            InterpreterError::Trap(ref trap) if matches!(trap.kind(), TrapKind::StackOverflow) => {
+                ExecError::StackOverflow
+            }
+            e => ExecError::Trap(e.to_string()),
+        }
+    }
+}
+
+/// Errors raised outside of script execution.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ScoutError {
+    MalformedSsz(String),
+    InvalidWasm(String),
+    /// A block was addressed to a shard which is not being tracked.
+    UnknownShard(u64),
+    /// A host function was registered under a name which is already taken.
+    DuplicateHostFn(String),
+    Io(String),
+    BlockProcessing(BlockProcessingError),
+}
+
+impl fmt::Display for ScoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScoutError::MalformedSsz(reason) => write!(f, "malformed SSZ: {}", reason),
+            ScoutError::InvalidWasm(reason) => write!(f, "invalid wasm: {}", reason),
+            ScoutError::UnknownShard(id) => write!(f, "unknown shard {}", id),
+            ScoutError::DuplicateHostFn(name) => write!(f, "duplicate host function {}", name),
+            ScoutError::Io(reason) => write!(f, "I/O error: {}", reason),
+            ScoutError::BlockProcessing(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ScoutError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ScoutError::BlockProcessing(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<BlockProcessingError> for ScoutError {
+    fn from(e: BlockProcessingError) -> Self {
+        ScoutError::BlockProcessing(e)
+    }
+}
+
+/// An error raised while processing a shard block, with the block it happened in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockProcessingError {
+    pub slot: u64,
+    pub env: u64,
+    pub source: ExecError,
+}
+
+impl fmt::Display for BlockProcessingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "block for env {} at slot {} failed: {}",
+            self.env, self.slot, self.source
+        )
+    }
+}
+
+impl std::error::Error for BlockProcessingError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+pub fn execute_code(
+    code: &[u8],
+    pre_state: &Bytes32,
+    block_data: &ShardBlockBody,
+) -> Result<ExecutionReport, ExecError> {
+    execute_code_with_config(code, pre_state, block_data, &ExecutionConfig::default())
+}
+
+pub fn execute_code_with_config(
+    code: &[u8],
+    pre_state: &Bytes32,
+    block_data: &ShardBlockBody,
+    config: &ExecutionConfig,
+) -> Result<ExecutionReport, ExecError> {
+    execute_code_in_context(code, pre_state, block_data, config, CallContext::default())
+}
+
+/// Executes the code, handing each deposit to `deposit_sink` as soon as the script pushes it
+/// rather than only once execution completes.
+pub fn execute_code_with_deposit_sink(
+    code: &[u8],
+    pre_state: &Bytes32,
+    block_data: &ShardBlockBody,
+    config: &ExecutionConfig,
+    deposit_sink: &mut dyn FnMut(Deposit),
+) -> Result<ExecutionReport, ExecError> {
+    execute(
+        code,
+        pre_state,
+        block_data,
+        config,
+        CallContext::default(),
+        Some(deposit_sink),
+    )
+}
+
+/// Executes the code with the given environments reachable through `eth2_callEnv`.
+pub fn execute_code_in_context(
+    code: &[u8],
+    pre_state: &Bytes32,
+    block_data: &ShardBlockBody,
+    config: &ExecutionConfig,
+    context: CallContext,
+) -> Result<ExecutionReport, ExecError> {
+    execute(code, pre_state, block_data, config, context, None)
+}
+
+/// Links the module against the host functions, returning the instance, which is not started
+/// yet, and its exported memory.
+fn instantiate<'m>(
+    module: &'m Module,
+    config: &ExecutionConfig,
+) -> Result<(NotStartedModuleRef<'m>, MemoryRef), ExecError> {
+    if config.reject_float {
+        module
+            .deny_floating_point()
+            .map_err(|_| ExecError::FloatingPointForbidden)?;
+    }
+    let instance = if config.host_functions.functions.is_empty() {
+        ModuleInstance::new(module, &RuntimeModuleImportResolver)
+    } else {
+        let resolver = HostFunctionsResolver {
+            host_functions: &config.host_functions,
+        };
+        ModuleInstance::new(module, &resolver)
+    }
+    .map_err(|e| ExecError::Instantiation(e.to_string()))?;
+    if instance.has_start() && !config.run_start {
+        return Err(ExecError::UnexpectedStart);
+    }
+
+    let memory = instance
+        .not_started_instance()
+        .export_by_name("memory")
+        .and_then(|export| export.as_memory().cloned())
+        .ok_or_else(|| ExecError::MissingExport("memory".to_string()))?;
+
+    Ok((instance, memory))
+}
+
+fn execute<'a>(
+    code: &'a [u8],
+    pre_state: &'a Bytes32,
+    block_data: &'a ShardBlockBody,
+    config: &'a ExecutionConfig,
+    context: CallContext<'a>,
+    deposit_sink: Option<&'a mut dyn FnMut(Deposit)>,
+) -> Result<ExecutionReport, ExecError> {
+    debug!(
+        code_size = code.len(),
+        data_size = block_data.data.len(),
+        "executing code"
+    );
+
+    let started = Instant::now();
+
+    if code.is_empty() && config.skip_empty_scripts {
+        return Ok(ExecutionReport {
+            post_state: *pre_state,
+            deposits: Vec::new(),
+            gas_used: 0,
+            host_calls: BTreeMap::new(),
+            execution_time: started.elapsed(),
+            trace: Vec::new(),
+            logs: Vec::new(),
+            peak_memory_pages: 0,
+        });
+    }
+
+    let (module, gas_global, call_depth_global) = wasm_load_from_blob(code, config)?;
+    let (not_started, internal_mem) = instantiate(&module, config)?;
+
+    let mut runtime = Runtime::new(code, pre_state, block_data, config, context)?;
+    runtime.deposit_sink = deposit_sink;
+
+    // wasmi zeroes memory when allocating and growing it, as the spec requires, so host reads
+    // of scratch space the script never wrote see zeros without clearing it here.
+    runtime.memory = Some(internal_mem.clone());
+    if !config.initial_memory.is_empty() {
+        runtime.write_memory(0, &config.initial_memory)?;
+    }
+
+    // Modules metered by the ewasm tooling keep the remaining gas in a mutable `gas` global.
+    let gas_global = gas_global.and_then(|index| {
+        not_started
+            .not_started_instance()
+            .globals()
+            .get(index as usize)
+            .cloned()
+    });
+    let gas_remaining = |global: &GlobalRef| match global.get() {
+        RuntimeValue::I64(gas) => gas as u64,
+        RuntimeValue::I32(gas) => u64::from(gas as u32),
+        _ => 0,
+    };
+    let initial_gas = gas_global.as_ref().map(gas_remaining);
+
+    // The depth only exceeds the limit once the injected check trapped.
+    let call_depth_global = call_depth_global.and_then(|index| {
+        not_started
+            .not_started_instance()
+            .globals()
+            .get(index as usize)
+            .cloned()
+    });
+    let call_depth_exceeded = || {
+        call_depth_global
+            .as_ref()
+            .is_some_and(|global| match global.get() {
+                RuntimeValue::I32(depth) => depth as u32 as usize > config.call_stack_limit,
+                _ => false,
+            })
+    };
+
+    let into_exec_error = |e: InterpreterError| {
+        let diagnostic = config.capture_diagnostics.then(|| Diagnostic::new(&e));
+        let error = if call_depth_exceeded() {
+            ExecError::CallDepthExceeded
+        } else {
+            ExecError::from(e)
+        };
+        match (error, diagnostic) {
+            // Errors of called environments arrive already diagnosed.
+            (error @ ExecError::Diagnosed(..), _) | (error, None) => error,
+            (error, Some(diagnostic)) => ExecError::Diagnosed(Box::new(error), diagnostic),
+        }
+    };
+    // `instantiate` already rejected a start function unless `run_start` is set.
+    let instance = if not_started.has_start() {
+        not_started
+            .run_start(&mut runtime)
+            .map_err(|trap| into_exec_error(trap.into()))?
+    } else {
+        not_started.assert_no_start()
+    };
+
+    let entry_point = config
+        .entry_points
+        .iter()
+        .find(|name| {
+            instance
+                .export_by_name(name)
+                .is_some_and(|export| export.as_func().is_some())
+        })
+        .ok_or_else(|| ExecError::MissingExport(config.entry_points.join(" or ")))?;
+    let mut stack = StackRecycler::with_limits(
+        config.value_stack_limit,
+        config.call_stack_limit.saturating_add(CALL_STACK_HEADROOM),
+    );
+    let result = instance
+        .invoke_export_with_stack(entry_point, &[], &mut runtime, &mut stack)
+        .map_err(into_exec_error)?;
+
+    debug!(?result, "execution finished");
+
+    if config.reject_unconsumed_data && !runtime.block_data_fully_consumed() {
+        return Err(ExecError::UnconsumedBlockData);
+    }
+    if config.require_post_state && runtime.post_state.is_none() {
+        return Err(ExecError::NoPostState);
+    }
+
+    Ok(ExecutionReport {
+        post_state: runtime.get_post_state(),
+        deposits: runtime.deposits,
+        gas_used: match (&gas_global, initial_gas) {
+            (Some(global), Some(initial)) => initial.saturating_sub(gas_remaining(global)),
+            _ => runtime.gas_used,
+        },
+        host_calls: runtime.host_calls,
+        execution_time: started.elapsed(),
+        trace: runtime.trace,
+        logs: runtime.logs,
+        peak_memory_pages: internal_mem.current_size().0 as u32,
+    })
+}
+
+/// Runs the same code and block against each of the given pre-states, as if it was
+/// deployed as several execution environments. Useful for script authors comparing outputs.
+pub fn execute_across_envs(
+    code: &[u8],
+    pre_states: &[Bytes32],
+    block_data: &ShardBlockBody,
+) -> Vec<Result<Bytes32, ExecError>> {
+    pre_states
+        .iter()
+        .map(|pre_state| execute_code(code, pre_state, block_data).map(|report| report.post_state))
+        .collect()
+}
+
+/// Supplies the pre-state of an execution environment to `process_shard_block_with_provider`.
+pub trait PreStateProvider {
+    fn pre_state(&self, env: usize) -> Bytes32;
+}
+
+/// The default provider, reading the environment states kept in the shard state.
+impl<S: EnvStateStore> PreStateProvider for ShardState<S> {
+    fn pre_state(&self, env: usize) -> Bytes32 {
+        self.exec_env_states.get(env)
+    }
+}
+
+/// Verifies the proposer signature of a block before `process_shard_block_with_verifier`
+/// applies it.
+pub trait BlockSignatureVerifier: fmt::Debug + Send + Sync {
+    fn verify(&self, block: &ShardBlock) -> Result<(), ExecError>;
+}
+
+/// Accepts every block, until blocks carry signatures.
+#[derive(Default, Clone, Copy, Debug)]
+pub struct NoopVerifier;
+
+impl BlockSignatureVerifier for NoopVerifier {
+    fn verify(&self, _block: &ShardBlock) -> Result<(), ExecError> {
+        Ok(())
+    }
+}
+
+/// Applies the block, or an empty slot, and returns the environments whose state changed.
+/// A failing block leaves the state untouched: the post-state and deposits of an execution
+/// stay in its runtime until it succeeds, and nothing is written to the shard state before.
+pub fn process_shard_block<S: EnvStateStore>(
+    state: &mut ShardState<S>,
+    beacon_state: &BeaconState,
+    block: Option<ShardBlock>,
+    config: &ExecutionConfig,
+    metrics: &mut Metrics,
+) -> Result<Vec<usize>, BlockProcessingError> {
+    process_shard_block_inner(
+        state,
+        beacon_state,
+        block,
+        config,
+        metrics,
+        BlockHooks::default(),
+    )
+}
+
+/// Like `process_shard_block`, but takes the pre-state of the executed environment from
+/// `provider` instead of the shard state.
+pub fn process_shard_block_with_provider<S: EnvStateStore>(
+    state: &mut ShardState<S>,
+    beacon_state: &BeaconState,
+    block: Option<ShardBlock>,
+    config: &ExecutionConfig,
+    metrics: &mut Metrics,
+    provider: &dyn PreStateProvider,
+) -> Result<Vec<usize>, BlockProcessingError> {
+    let hooks = BlockHooks {
+        provider: Some(provider),
+        ..Default::default()
+    };
+    process_shard_block_inner(state, beacon_state, block, config, metrics, hooks)
+}
+
+/// Like `process_shard_block`, but rejects the block with the error of `verifier` before
+/// executing it.
+pub fn process_shard_block_with_verifier<S: EnvStateStore>(
+    state: &mut ShardState<S>,
+    beacon_state: &BeaconState,
+    block: Option<ShardBlock>,
+    config: &ExecutionConfig,
+    metrics: &mut Metrics,
+    verifier: &dyn BlockSignatureVerifier,
+) -> Result<Vec<usize>, BlockProcessingError> {
+    let hooks = BlockHooks {
+        verifier: Some(verifier),
+        ..Default::default()
+    };
+    process_shard_block_inner(state, beacon_state, block, config, metrics, hooks)
+}
+
+/// Like `process_shard_block`, recording the writes to the state in `journal` so they can
+/// be undone.
+pub fn process_shard_block_journaled<S: EnvStateStore>(
+    state: &mut ShardState<S>,
+    beacon_state: &BeaconState,
+    block: Option<ShardBlock>,
+    config: &ExecutionConfig,
+    metrics: &mut Metrics,
+    journal: &mut ShardStateJournal,
+) -> Result<Vec<usize>, BlockProcessingError> {
+    let hooks = BlockHooks {
+        journal: Some(journal),
+        ..Default::default()
+    };
+    process_shard_block_inner(state, beacon_state, block, config, metrics, hooks)
+}
+
+/// What the public variants of `process_shard_block` change about it. The defaults read the
+/// pre-states from the shard state, accept every block and record no journal.
+#[derive(Default)]
+struct BlockHooks<'a> {
+    provider: Option<&'a dyn PreStateProvider>,
+    verifier: Option<&'a dyn BlockSignatureVerifier>,
+    journal: Option<&'a mut ShardStateJournal>,
+}
+
+fn process_shard_block_inner<S: EnvStateStore>(
+    state: &mut ShardState<S>,
+    beacon_state: &BeaconState,
+    block: Option<ShardBlock>,
+    config: &ExecutionConfig,
+    metrics: &mut Metrics,
+    mut hooks: BlockHooks,
+) -> Result<Vec<usize>, BlockProcessingError> {
+    debug!(?block, "executing block");
+
+    // Only the executed environment and the slot change, so the diff is built from those
+    // rather than by comparing against a copy of the whole state.
+    let pre_slot = state.slot;
+    let mut changed_envs = Vec::new();
+
+    // TODO: implement state root handling
+
+    if let Some(block) = block {
+        hooks
+            .verifier
+            .unwrap_or(&NoopVerifier)
+            .verify(&block)
+            .map_err(|source| BlockProcessingError {
+                slot: state.slot,
+                env: block.env,
+                source,
+            })?;
+
+        // The execution environment identifier, which comes from the block and may be
+        // out of range or not even fit a usize.
+        let unknown_env = || BlockProcessingError {
+            slot: state.slot,
+            env: block.env,
+            source: ExecError::UnknownEnvironment(block.env),
+        };
+        let env = usize::try_from(block.env).map_err(|_| unknown_env())?;
+        let code = &beacon_state
+            .execution_scripts
+            .get(env)
+            .ok_or_else(unknown_env)?
+            .code;
+
+        let pre_state = match hooks.provider {
+            Some(provider) => provider.pre_state(env),
+            None => state.pre_state(env),
+        };
+        let config = config.clone().with_slot(state.slot);
+        let context = CallContext {
+            scripts: &beacon_state.execution_scripts,
+            states: &state.exec_env_states,
+            latest_state_roots: &state.latest_state_roots,
+            parent_root: state.parent_root(),
+            depth: 0,
+        };
+        let report = execute_code_in_context(code, &pre_state, &block.data, &config, context)
+            .and_then(|report| {
+                // Unlike `max_deposits`, which guards the host, this is a consensus rule.
+                if report.deposits.len() > MAX_DEPOSITS_PER_BLOCK {
+                    return Err(ExecError::TooManyDeposits(report.deposits.len()));
+                }
+                Ok(report)
+            })
+            .map_err(|source| BlockProcessingError {
+                slot: state.slot,
+                env: block.env,
+                source,
+            })?;
+        metrics.record_execution(&report);
+        if let Some(journal) = hooks.journal.as_deref_mut() {
+            journal.entries.push(JournalEntry::EnvState {
+                env,
+                previous: state.exec_env_states.get(env),
+                previous_len: state.exec_env_states.len(),
+            });
+            journal
+                .entries
+                .push(JournalEntry::ParentBlock(state.parent_block.clone()));
+        }
+        // Environments skipped over are added with zero roots, which the diff leaves out.
+        let old = state.exec_env_states.get(env);
+        state.exec_env_states.set(env, report.post_state);
+        if old != report.post_state {
+            changed_envs.push((env, old, report.post_state));
+        }
+        state.parent_block = Some(ShardBlockHeader {
+            slot: state.slot,
+            parent_root: state.parent_root(),
+            state_root: state.state_root(),
+            body_root: block.root(),
+        });
+    }
+
+    // TODO: implement deposit root handling
+
+    let evicted = state.advance_slot();
+    if let Some(journal) = hooks.journal {
+        journal.entries.push(JournalEntry::AdvanceSlot(evicted));
+    }
+
+    let diff = StateDiff {
+        changed_envs,
+        slot: (pre_slot, state.slot),
+    };
+    info!("State changes:\n{}", diff);
+
+    Ok(diff.changed_envs.iter().map(|(env, _, _)| *env).collect())
+}
+
+/// Applies the block to a copy of the state and returns the resulting state, leaving the
+/// given one untouched.
+pub fn simulate_shard_block<S: EnvStateStore + Clone>(
+    state: &ShardState<S>,
+    beacon_state: &BeaconState,
+    block: Option<ShardBlock>,
+    config: &ExecutionConfig,
+) -> Result<ShardState<S>, BlockProcessingError> {
+    let mut state = state.clone();
+    process_shard_block(
+        &mut state,
+        beacon_state,
+        block,
+        config,
+        &mut Metrics::default(),
+    )?;
+    Ok(state)
+}
+
+/// The state root after applying the block, without changing the given state.
+pub fn transition_root<S: EnvStateStore + Clone>(
+    state: &ShardState<S>,
+    beacon_state: &BeaconState,
+    block: Option<ShardBlock>,
+) -> Result<Bytes32, ExecError> {
+    simulate_shard_block(state, beacon_state, block, &ExecutionConfig::default())
+        .map(|post_state| post_state.state_root())
+        .map_err(|e| e.source)
+}
+
+/// What `process_shard_blocks` does when a block fails to execute.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BatchPolicy {
+    /// Stop at the first failing block and return its error.
+    #[default]
+    StopOnError,
+    /// Leave failing blocks unapplied, continue with the rest and collect the failures.
+    SkipAndContinue,
+    /// Process the slot of a failing block as empty, then continue with the rest and collect
+    /// the failures. A failing block writes nothing, so its environment keeps its prior state
+    /// under `SkipAndContinue` as well; the difference is that here the slot still advances,
+    /// so the following blocks are applied at the slots they would have been without it.
+    RollbackAndContinue,
+    /// Stop at the first failing block and return its error, after undoing the blocks of the
+    /// batch applied before it through a `ShardStateJournal`, so the batch is all or nothing.
+    /// A block which fails to decode reverts the batch as well.
+    RevertOnError,
+}
+
+/// Processes a batch of shard blocks in order, pulling them one at a time so a `BlockReader`
+/// can stream them. Under `BatchPolicy::SkipAndContinue` the index and error of every skipped
+/// block is returned, a block which fails to decode stops the batch under every policy.
+pub fn process_shard_blocks<S, I>(
+    state: &mut ShardState<S>,
+    beacon_state: &BeaconState,
+    blocks: I,
+    config: &ExecutionConfig,
+    metrics: &mut Metrics,
+    policy: BatchPolicy,
+) -> Result<Vec<(usize, ExecError)>, ScoutError>
+where
+    S: EnvStateStore,
+    I: IntoIterator<Item = Result<ShardBlock, ScoutError>>,
+{
+    process_shard_blocks_inner(
+        state,
+        blocks,
+        policy,
+        |state: &mut ShardState<S>, block, journal| {
+            let hooks = BlockHooks {
+                journal,
+                ..Default::default()
+            };
+            process_shard_block_inner(state, beacon_state, block, config, metrics, hooks)
+        },
+        &mut |_| {},
+    )
+}
+
+/// Like `process_shard_blocks`, verifying every block with `verifier` before it is applied.
+/// A rejected block is a failing block, handled as `policy` says.
+pub fn process_shard_blocks_with_verifier<S, I>(
+    state: &mut ShardState<S>,
+    beacon_state: &BeaconState,
+    blocks: I,
+    config: &ExecutionConfig,
+    metrics: &mut Metrics,
+    policy: BatchPolicy,
+    verifier: &dyn BlockSignatureVerifier,
+) -> Result<Vec<(usize, ExecError)>, ScoutError>
+where
+    S: EnvStateStore,
+    I: IntoIterator<Item = Result<ShardBlock, ScoutError>>,
+{
+    process_shard_blocks_inner(
+        state,
+        blocks,
+        policy,
+        |state: &mut ShardState<S>, block, journal| {
+            let hooks = BlockHooks {
+                verifier: Some(verifier),
+                journal,
+                ..Default::default()
+            };
+            process_shard_block_inner(state, beacon_state, block, config, metrics, hooks)
+        },
+        &mut |_| {},
+    )
+}
+
+/// Like `process_shard_blocks`, calling `on_epoch_boundary` with the number of the new epoch
+/// whenever the shard enters one, every `SLOTS_PER_EPOCH` slots.
+pub fn process_shard_blocks_with_epoch_hook<S, I>(
+    state: &mut ShardState<S>,
+    beacon_state: &BeaconState,
+    blocks: I,
+    config: &ExecutionConfig,
+    metrics: &mut Metrics,
+    policy: BatchPolicy,
+    on_epoch_boundary: &mut dyn FnMut(u64),
+) -> Result<Vec<(usize, ExecError)>, ScoutError>
+where
+    S: EnvStateStore,
+    I: IntoIterator<Item = Result<ShardBlock, ScoutError>>,
+{
+    process_shard_blocks_inner(
+        state,
+        blocks,
+        policy,
+        |state: &mut ShardState<S>, block, journal| {
+            let hooks = BlockHooks {
+                journal,
+                ..Default::default()
+            };
+            process_shard_block_inner(state, beacon_state, block, config, metrics, hooks)
+        },
+        on_epoch_boundary,
+    )
+}
+
+/// Applies `blocks` in order with `apply`, which stands for one of the `process_shard_block`
+/// variants and records its writes in the journal it is given, if any.
+fn process_shard_blocks_inner<S, I, F>(
+    state: &mut ShardState<S>,
+    blocks: I,
+    policy: BatchPolicy,
+    mut apply: F,
+    on_epoch_boundary: &mut dyn FnMut(u64),
+) -> Result<Vec<(usize, ExecError)>, ScoutError>
+where
+    S: EnvStateStore,
+    I: IntoIterator<Item = Result<ShardBlock, ScoutError>>,
+    F: FnMut(
+        &mut ShardState<S>,
+        Option<ShardBlock>,
+        Option<&mut ShardStateJournal>,
+    ) -> Result<Vec<usize>, BlockProcessingError>,
+{
+    // Only kept when it may be needed, so the other policies do not pay for recording.
+    let mut journal = match policy {
+        BatchPolicy::RevertOnError => Some(ShardStateJournal::default()),
+        _ => None,
+    };
+    let mut failures = Vec::new();
+    for (index, block) in blocks.into_iter().enumerate() {
+        let epoch = state.slot / SLOTS_PER_EPOCH;
+        let block = match block {
+            Ok(block) => block,
+            Err(error) => {
+                if let Some(journal) = journal.as_mut() {
+                    journal.undo(state);
+                }
+                return Err(error);
+            }
+        };
+        if let Err(error) = apply(state, Some(block), journal.as_mut()) {
+            match policy {
+                BatchPolicy::StopOnError => return Err(error.into()),
+                BatchPolicy::SkipAndContinue => failures.push((index, error.source)),
+                BatchPolicy::RollbackAndContinue => {
+                    apply(state, None, None)?;
+                    failures.push((index, error.source));
+                }
+                BatchPolicy::RevertOnError => {
+                    if let Some(journal) = journal.as_mut() {
+                        journal.undo(state);
+                    }
+                    return Err(error.into());
+                }
+            }
+        }
+        if state.slot / SLOTS_PER_EPOCH > epoch {
+            on_epoch_boundary(state.slot / SLOTS_PER_EPOCH);
+        }
+    }
+    Ok(failures)
+}
+
+#[derive(Clone, Debug)]
+pub struct Shard {
+    pub id: u64,
+    pub state: ShardState,
+}
+
+/// The shards simulated in one process, each with its own state. Blocks are processed per
+/// shard, there is no cross-shard communication.
+#[derive(Default, Clone, Debug)]
+pub struct Shards {
+    shards: BTreeMap<u64, Shard>,
+}
+
+impl Shards {
+    /// Starts every shard in `ids` from a copy of `genesis`.
+    pub fn from_genesis<I: IntoIterator<Item = u64>>(ids: I, genesis: &ShardState) -> Shards {
+        let shards = ids
+            .into_iter()
+            .map(|id| {
+                let state = genesis.clone();
+                (id, Shard { id, state })
+            })
+            .collect();
+        Shards { shards }
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Shard> {
+        self.shards.get(&id)
+    }
+
+    pub fn process_block(
+        &mut self,
+        id: u64,
+        beacon_state: &BeaconState,
+        block: Option<ShardBlock>,
+        config: &ExecutionConfig,
+        metrics: &mut Metrics,
+    ) -> Result<(), ScoutError> {
+        let shard = self
+            .shards
+            .get_mut(&id)
+            .ok_or(ScoutError::UnknownShard(id))?;
+        process_shard_block(&mut shard.state, beacon_state, block, config, metrics)?;
+        Ok(())
+    }
+}
+
+/// Parses a hex encoded 32-byte value, as given on the command line.
+fn parse_bytes32(hex: &str) -> Result<Bytes32, String> {
+    let bytes: Bytes = hex.parse()?;
+    bytes.check_len(32)?;
+    let mut state = Bytes32::default();
+    state.bytes.copy_from_slice(&bytes);
+    Ok(state)
+}
+
+/// Parses block data given in hex, with `-` for an empty block.
+fn parse_block_data(hex: &str) -> Result<Bytes, String> {
+    match hex {
+        "-" => Ok(Bytes::default()),
+        hex => hex.parse(),
+    }
+}
+
+#[derive(Parser)]
+#[command(name = "scout", about = "Eth 2.0 Phase 2 execution prototyping engine")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Executes a script on a single block and writes the resulting state root and metrics.
+    Run {
+        #[arg(long, default_value = "phase2_helloworld.wasm")]
+        code: PathBuf,
+        /// Pre-state of the script in hex, zero by default.
+        #[arg(long, value_parser = parse_bytes32)]
+        pre_state: Option<Bytes32>,
+        /// Block data in hex.
+        #[arg(long, default_value = "-", value_parser = parse_block_data)]
+        data: Bytes,
+        /// Fail once the script uses more gas than this.
+        #[arg(long)]
+        gas_limit: Option<u64>,
+        /// Write the results to this file instead of stdout.
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Validates a script and prints its imports, exports and memory limits.
+    Inspect {
+        #[arg(long)]
+        code: PathBuf,
+        /// Also print the module in the text format.
+        #[arg(long)]
+        disassemble: bool,
+    },
+    /// Processes a stream of blocks encoded by `ShardBlock::to_ssz` against a beacon state.
+    Replay {
+        #[arg(long)]
+        input: PathBuf,
+        #[arg(long)]
+        beacon_state: PathBuf,
+        /// Skip blocks which fail instead of stopping at the first one.
+        #[arg(long)]
+        skip_errors: bool,
+        /// Write the results to this file instead of stdout.
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Loads the scripts in a directory as a beacon state and prints its root.
+    BeaconRoot {
+        #[arg(long)]
+        dir: PathBuf,
+    },
+}
+
+type CliResult = Result<(), Box<dyn std::error::Error>>;
+
+fn run(
+    code: &Path,
+    pre_state: Option<Bytes32>,
+    data: Bytes,
+    gas_limit: Option<u64>,
+    output: Option<&Path>,
+) -> CliResult {
+    let pre_state = pre_state.unwrap_or(ZERO_HASH);
+    let mut shard_state = ShardState {
+        exec_env_states: vec![pre_state],
+        slot: 0,
+        parent_block: None,
+        latest_state_roots: vec![],
+    };
+    let beacon_state = BeaconState {
+        execution_scripts: vec![ExecutionScript::from_file(code)?],
+    };
+    let shard_block = ShardBlock {
+        env: 0,
+        data: ShardBlockBody { data },
+    };
+    let config = ExecutionConfig {
+        gas_limit,
+        ..Default::default()
+    };
+    let mut metrics = Metrics::default();
+    process_shard_block(
+        &mut shard_state,
+        &beacon_state,
+        Some(shard_block),
+        &config,
+        &mut metrics,
+    )?;
+    write_output(output, &shard_state, &metrics)
+}
+
+fn inspect(code: &Path, disassemble_code: bool) -> CliResult {
+    let code = ExecutionScript::from_file(code)?.code;
+    let info = validate_wasm(&code)?;
+    println!("functions: {}", info.functions);
+    println!("imports: {}", info.imports);
+    println!("exports: {}", info.exports);
+    match info.memory {
+        Some((initial, Some(maximum))) => println!("memory: {} to {} pages", initial, maximum),
+        Some((initial, None)) => println!("memory: {} pages, unbounded", initial),
+        None => println!("memory: none"),
+    }
+    if disassemble_code {
+        println!("{}", disassemble(&code)?);
+    }
+    Ok(())
+}
+
+fn replay(
+    input: &Path,
+    beacon_state: &Path,
+    skip_errors: bool,
+    output: Option<&Path>,
+) -> CliResult {
+    let beacon_state = BeaconState::load(beacon_state)?;
+    for (env, result) in beacon_state.preflight() {
+        if let Err(error) = result {
+            warn!(env, %error, "execution environment does not instantiate");
+        }
+    }
+    let mut shard_state = ShardState {
+        exec_env_states: vec![ZERO_HASH; beacon_state.execution_scripts.len()],
+        ..Default::default()
+    };
+    let policy = if skip_errors {
+        BatchPolicy::SkipAndContinue
+    } else {
+        BatchPolicy::StopOnError
+    };
+    let mut metrics = Metrics::default();
+    let failures = process_shard_blocks_with_epoch_hook(
+        &mut shard_state,
+        &beacon_state,
+        BlockReader::new(BufReader::new(File::open(input)?)),
+        &ExecutionConfig::default(),
+        &mut metrics,
+        policy,
+        &mut |epoch| info!(epoch, "entered epoch"),
+    )?;
+    for (index, error) in failures {
+        eprintln!("skipped block {}: {}", index, error);
+    }
+    write_output(output, &shard_state, &metrics)
+}
+
+fn beacon_root(dir: &Path) -> CliResult {
+    let beacon_state = BeaconState::from_dir(dir)?;
+    println!("beacon_root: {}", beacon_state.root());
+    Ok(())
+}
+
+fn main() {
+    let cli = Cli::try_parse().unwrap_or_else(|e| {
+        let _ = e.print();
+        // Help and version requests are printed to stdout and are not failures.
+        std::process::exit(if e.use_stderr() { EXIT_USAGE } else { 0 });
+    });
+
+    // Logs go to stderr, so stdout only carries the results. `RUST_LOG` overrides the level.
+    tracing_subscriber::fmt()
+        .with_writer(io::stderr)
+        .with_env_filter(
+            EnvFilter::builder()
+                .with_default_directive(LevelFilter::INFO.into())
+                .from_env_lossy(),
+        )
+        .init();
+
+    let result = match &cli.command {
+        Command::Run {
+            code,
+            pre_state,
+            data,
+            gas_limit,
+            output,
+        } => run(
+            code,
+            *pre_state,
+            data.clone(),
+            *gas_limit,
+            output.as_deref(),
+        ),
+        Command::Inspect { code, disassemble } => inspect(code, *disassemble),
+        Command::Replay {
+            input,
+            beacon_state,
+            skip_errors,
+            output,
+        } => replay(input, beacon_state, *skip_errors, output.as_deref()),
+        Command::BeaconRoot { dir } => beacon_root(dir),
+    };
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        std::process::exit(exit_code(e.as_ref()));
+    }
+}
+
+/// Exit code for invalid arguments.
+const EXIT_USAGE: i32 = 2;
+/// Exit code for failing to read inputs or write outputs.
+const EXIT_IO: i32 = 3;
+/// Exit code for scripts or blocks which failed to execute.
+const EXIT_EXECUTION: i32 = 4;
+
+/// Maps a failed subcommand to its exit code.
+fn exit_code(e: &(dyn std::error::Error + 'static)) -> i32 {
+    if e.is::<io::Error>() {
+        return EXIT_IO;
+    }
+    match e.downcast_ref::<ScoutError>() {
+        Some(ScoutError::Io(_)) | Some(ScoutError::MalformedSsz(_)) => EXIT_IO,
+        _ => EXIT_EXECUTION,
+    }
+}
+
+/// Writes the results to `output`, or to stdout without one.
+fn write_output(output: Option<&Path>, state: &ShardState, metrics: &Metrics) -> CliResult {
+    match output {
+        Some(path) => {
+            let mut writer = BufWriter::new(File::create(path)?);
+            write_results(&mut writer, state, metrics)?;
+            writer.flush()?;
+        }
+        None => write_results(&mut io::stdout(), state, metrics)?,
+    }
+    Ok(())
+}
+
+/// Writes the machine-readable outcome of a run: the final state root and the metrics.
+fn write_results<W: Write>(out: &mut W, state: &ShardState, metrics: &Metrics) -> io::Result<()> {
+    writeln!(out, "state_root: {}", state.state_root())?;
+    writeln!(out, "gas used: {}", metrics.gas_used())?;
+    write!(out, "{}", metrics.metrics_text())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_modules::*;
+
+    #[test]
+    fn execute_across_envs_distinct_pre_states() {
+        let code = loadstore_module();
+        let pre_states = [Bytes32::from([1u8; 32]), Bytes32::from([2u8; 32])];
+        let block_data = ShardBlockBody::default();
+
+        let results = execute_across_envs(&code, &pre_states, &block_data);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].as_ref().unwrap().bytes, [1u8; 32]);
+        assert_eq!(results[1].as_ref().unwrap().bytes, [2u8; 32]);
+    }
+
+    #[test]
+    fn cancel_running_execution() {
+        let code = build_module(
+            r#"
+            (module
+              (memory (export "memory") 1)
+              (func (export "main")
+                (loop $forever (br $forever))))
+            "#,
+        );
+        let flag = Arc::new(AtomicBool::new(false));
+        let config = ExecutionConfig::default().with_cancel_flag(flag.clone());
+
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            flag.store(true, Ordering::Relaxed);
+        });
+        let result = execute_code_with_config(
+            &code,
+            &Bytes32::default(),
+            &ShardBlockBody::default(),
+            &config,
+        );
+        canceller.join().unwrap();
+
+        assert_eq!(result.unwrap_err(), ExecError::Cancelled);
+    }
+
+    /// Builds a script assembling a single deposit from the given `(offset, length)` writes.
+    /// Memory holds a deposit with pubkey bytes 0x01, credentials 0x02, amount 32 and
+    /// signature bytes 0x03.
+    fn deposit_script(writes: &[(u32, u32)]) -> Vec<u8> {
+        let data = format!(
+            "{}{}\\20\\00\\00\\00\\00\\00\\00\\00{}",
+            "\\01".repeat(48),
+            "\\02".repeat(32),
+            "\\03".repeat(96)
+        );
+        let calls: String = writes
+            .iter()
+            .map(|(ptr, len)| {
+                format!(
+                    "(call $depositWrite (i32.const {}) (i32.const {}))",
+                    ptr, len
+                )
+            })
+            .collect();
+        build_module(&format!(
+            r#"
+            (module
+              (import "env" "eth2_beginDeposit" (func $beginDeposit))
+              (import "env" "eth2_depositWrite" (func $depositWrite (param i32 i32)))
+              (import "env" "eth2_endDeposit" (func $endDeposit))
+              (memory (export "memory") 1)
+              (data (i32.const 0) "{}")
+              (func (export "main")
+                (call $beginDeposit)
+                {}
+                (call $endDeposit)))
+            "#,
+            data, calls
+        ))
+    }
+
+    #[test]
+    fn assemble_deposit_incrementally() {
+        let code = deposit_script(&[(0, 48), (48, 40), (88, 96)]);
+        let deposits = execute_code(&code, &Bytes32::default(), &ShardBlockBody::default())
+            .unwrap()
+            .deposits;
+
+        assert_eq!(deposits.len(), 1);
+        assert_eq!(deposits[0].pubkey, [1u8; 48]);
+        assert_eq!(deposits[0].withdrawal_credentials.bytes, [2u8; 32]);
+        assert_eq!(deposits[0].amount, 32);
+        assert_eq!(deposits[0].signature[..], [3u8; 96][..]);
+    }
+
+    #[test]
+    fn deposit_staged_len() {
+        // Saves the staged length before the deposit and after each write as one byte each.
+        let code = build_module(
+            r#"
+            (module
+              (import "env" "eth2_beginDeposit" (func $beginDeposit))
+              (import "env" "eth2_depositWrite" (func $depositWrite (param i32 i32)))
+              (import "env" "eth2_depositStagedLen" (func $depositStagedLen (result i32)))
+              (import "env" "eth2_savePostState" (func $savePostState (param i32)))
+              (memory (export "memory") 1)
+              (func (export "main")
+                (i32.store8 (i32.const 256) (call $depositStagedLen))
+                (call $beginDeposit)
+                (i32.store8 (i32.const 257) (call $depositStagedLen))
+                (call $depositWrite (i32.const 0) (i32.const 48))
+                (i32.store8 (i32.const 258) (call $depositStagedLen))
+                (call $depositWrite (i32.const 0) (i32.const 40))
+                (i32.store8 (i32.const 259) (call $depositStagedLen))
+                (call $savePostState (i32.const 256))))
+            "#,
+        );
+        let report = execute_code(&code, &Bytes32::default(), &ShardBlockBody::default()).unwrap();
+        assert_eq!(report.post_state.bytes[..4], [0, 0, 48, 88]);
+    }
+
+    #[test]
+    fn deposit_write_overflow_traps() {
+        let code = deposit_script(&[(0, 184), (0, 1)]);
+        let result = execute_code(&code, &Bytes32::default(), &ShardBlockBody::default());
+        assert_eq!(
+            result.unwrap_err(),
+            ExecError::InvalidDeposit("deposit data overflow".into())
+        );
+    }
+
+    #[test]
+    fn report_counts_host_calls_and_gas() {
+        let code = loadstore_module();
+        let report = execute_code(&code, &Bytes32::default(), &ShardBlockBody::default()).unwrap();
+
+        assert_eq!(report.host_calls["eth2_loadPreState"], 1);
+        assert_eq!(report.host_calls["eth2_savePostState"], 1);
+        assert!(!report.host_calls.contains_key("gas"));
+        assert!(report.gas_used > 0);
+    }
+
+    #[test]
+    fn process_shard_block_updates_metrics() {
+        let code = loadstore_module();
+        let beacon_state = BeaconState {
+            execution_scripts: vec![ExecutionScript { code }],
+        };
+        let mut state = ShardState {
+            exec_env_states: vec![Bytes32::default()],
+            ..Default::default()
+        };
+        let mut metrics = Metrics::default();
+        let blocks_processed = |text: &str| -> u64 {
+            text.lines()
+                .find_map(|line| line.strip_prefix("scout_blocks_processed_total "))
+                .expect("block counter is exported")
+                .parse()
+                .unwrap()
+        };
+
+        let mut previous = blocks_processed(&metrics.metrics_text());
+        assert_eq!(previous, 0);
+        for expected in 1..=5 {
+            let block = ShardBlock::default();
+            process_shard_block(
+                &mut state,
+                &beacon_state,
+                Some(block),
+                &ExecutionConfig::default(),
+                &mut metrics,
+            )
+            .unwrap();
+            let text = metrics.metrics_text();
+            let count = blocks_processed(&text);
+            assert!(count > previous);
+            assert_eq!(count, expected);
+            previous = count;
+            assert!(text.contains("scout_gas_used_total"));
+            assert!(text.contains("scout_host_calls_total{function=\"eth2_loadPreState\"}"));
+            assert!(text.contains("scout_execution_time_seconds_average"));
+        }
+    }
+
+    #[test]
+    fn validate_wasm_reports_module_info() {
+        let code = loadstore_module();
+        let info = validate_wasm(&code).unwrap();
+        assert_eq!(
+            info,
+            WasmInfo {
+                functions: 1,
+                imports: 2,
+                exports: 2,
+                memory: Some((1, None)),
+            }
+        );
+    }
+
+    #[test]
+    fn disassemble_prints_exports() {
+        let code = loadstore_module();
+        let text = disassemble(&code).unwrap();
+        assert!(text.contains("(export \"main\""));
+        assert!(matches!(
+            disassemble(b"\0asm\x02\0\0\0"),
+            Err(ScoutError::InvalidWasm(_))
+        ));
+    }
+
+    #[test]
+    fn validate_wasm_rejects_malformed_modules() {
+        assert!(matches!(
+            validate_wasm(b"\0asm\x02\0\0\0"),
+            Err(ScoutError::InvalidWasm(_))
+        ));
+
+        // A function whose body leaves a value on the stack of a function without results.
+        let code = build_module("(module (func (i32.const 0)))");
+        assert!(matches!(
+            validate_wasm(&code),
+            Err(ScoutError::InvalidWasm(_))
+        ));
+    }
+
+    #[test]
+    fn shard_block_body_ssz_roundtrip() {
+        let body = ShardBlockBody {
+            data: vec![1, 2, 3].into(),
+        };
+        let encoded = body.to_ssz();
+        assert_eq!(encoded, [3, 0, 0, 0, 1, 2, 3]);
+        assert_eq!(ShardBlockBody::from_ssz(&encoded).unwrap().data, body.data);
+    }
+
+    #[test]
+    fn shard_block_body_ssz_malformed() {
+        let truncated = ShardBlockBody::from_ssz(&[3, 0]).unwrap_err();
+        assert!(matches!(truncated, ScoutError::MalformedSsz(_)));
+
+        let long_prefix = ShardBlockBody::from_ssz(&[0xff, 0xff, 0xff, 0xff, 1, 2]).unwrap_err();
+        assert!(matches!(long_prefix, ScoutError::MalformedSsz(_)));
+
+        let trailing = ShardBlockBody::from_ssz(&[1, 0, 0, 0, 1, 2]).unwrap_err();
+        assert!(matches!(trailing, ScoutError::MalformedSsz(_)));
+    }
+
+    #[test]
+    fn process_shard_block_with_custom_provider() {
+        struct FixedPreState;
+
+        impl PreStateProvider for FixedPreState {
+            fn pre_state(&self, _env: usize) -> Bytes32 {
+                Bytes32::from([9u8; 32])
+            }
+        }
+
+        let code = loadstore_module();
+        let beacon_state = BeaconState {
+            execution_scripts: vec![ExecutionScript { code }],
+        };
+        let mut state = ShardState {
+            exec_env_states: vec![Bytes32::default()],
+            ..Default::default()
+        };
+        process_shard_block_with_provider(
+            &mut state,
+            &beacon_state,
+            Some(ShardBlock::default()),
+            &ExecutionConfig::default(),
+            &mut Metrics::default(),
+            &FixedPreState,
+        )
+        .unwrap();
+        assert_eq!(state.exec_env_states[0], Bytes32::from([9u8; 32]));
+    }
+
+    #[test]
+    fn shards_are_processed_independently() {
+        let beacon_state = BeaconState {
+            execution_scripts: vec![ExecutionScript {
+                code: block_copy_script(0, 32),
+            }],
+        };
+        let genesis = ShardState {
+            exec_env_states: vec![Bytes32::default()],
+            ..Default::default()
+        };
+        let mut shards = Shards::from_genesis(0..2, &genesis);
+        let mut process = |id, byte| {
+            let block = ShardBlock {
+                env: 0,
+                data: ShardBlockBody {
+                    data: vec![byte; 32].into(),
+                },
+            };
+            shards.process_block(
+                id,
+                &beacon_state,
+                Some(block),
+                &ExecutionConfig::default(),
+                &mut Metrics::default(),
+            )
+        };
+
+        process(0, 1).unwrap();
+        process(1, 2).unwrap();
+        process(1, 3).unwrap();
+        assert_eq!(process(2, 4), Err(ScoutError::UnknownShard(2)));
+
+        let shard = shards.get(0).unwrap();
+        assert_eq!(
+            (shard.state.slot, shard.state.exec_env_states[0].bytes),
+            (1, [1; 32])
+        );
+        let shard = shards.get(1).unwrap();
+        assert_eq!(
+            (shard.state.slot, shard.state.exec_env_states[0].bytes),
+            (2, [3; 32])
+        );
+    }
+
+    #[test]
+    fn env_count_returns_script_count() {
+        let code = build_module(
+            r#"
+            (module
+              (import "env" "eth2_envCount" (func $envCount (result i32)))
+              (import "env" "eth2_savePostState" (func $savePostState (param i32)))
+              (memory (export "memory") 1)
+              (func (export "main")
+                (i32.store (i32.const 0) (call $envCount))
+                (call $savePostState (i32.const 0))))
+            "#,
+        );
+        let beacon_state = BeaconState {
+            execution_scripts: vec![ExecutionScript { code }; 3],
+        };
+        let mut state = ShardState {
+            exec_env_states: vec![Bytes32::default()],
+            ..Default::default()
+        };
+        process_shard_block(
+            &mut state,
+            &beacon_state,
+            Some(ShardBlock::default()),
+            &ExecutionConfig::default(),
+            &mut Metrics::default(),
+        )
+        .unwrap();
+        assert_eq!(state.exec_env_states[0].bytes[..4], [3, 0, 0, 0]);
+    }
+
+    #[test]
+    fn env_exists() {
+        // Saves the flags for environments 0, 1, 2 and -1 as one byte each.
+        let code = build_module(
+            r#"
+            (module
+              (import "env" "eth2_envExists" (func $envExists (param i32) (result i32)))
+              (import "env" "eth2_savePostState" (func $savePostState (param i32)))
+              (memory (export "memory") 1)
+              (func (export "main")
+                (i32.store8 (i32.const 0) (call $envExists (i32.const 0)))
+                (i32.store8 (i32.const 1) (call $envExists (i32.const 1)))
+                (i32.store8 (i32.const 2) (call $envExists (i32.const 2)))
+                (i32.store8 (i32.const 3) (call $envExists (i32.const -1)))
+                (call $savePostState (i32.const 0))))
+            "#,
+        );
+        let scripts = vec![ExecutionScript::default(); 2];
+        let context = CallContext {
+            scripts: &scripts,
+            ..Default::default()
+        };
+
+        let report = execute_code_in_context(
+            &code,
+            &Bytes32::default(),
+            &ShardBlockBody::default(),
+            &ExecutionConfig::default(),
+            context,
+        )
+        .unwrap();
+        assert_eq!(report.post_state.bytes[..4], [1, 1, 0, 0]);
+    }
+
+    #[test]
+    fn hex_encode() {
+        // Encodes the 4 bytes at 0 into 64, saving the result and the returned length.
+        let script = |dst_max: u32| {
+            build_module(&format!(
+                r#"
+                (module
+                  (import "env" "eth2_hexEncode" (func $hexEncode (param i32 i32 i32 i32) (result i32)))
+                  (import "env" "eth2_savePostState" (func $savePostState (param i32)))
+                  (memory (export "memory") 1)
+                  (data (i32.const 0) "\de\ad\be\ef")
+                  (func (export "main")
+                    (i32.store (i32.const 72) (call $hexEncode (i32.const 0) (i32.const 4) (i32.const 64) (i32.const {})))
+                    (call $savePostState (i32.const 64))))
+                "#,
+                dst_max
+            ))
+        };
+        let run = |dst_max: u32| {
+            execute_code(
+                &script(dst_max),
+                &Bytes32::default(),
+                &ShardBlockBody::default(),
+            )
+            .unwrap()
+            .post_state
+        };
+
+        let post_state = run(8);
+        assert_eq!(&post_state.bytes[..8], b"deadbeef");
+        assert_eq!(post_state.bytes[8..12], 8i32.to_le_bytes());
+        let post_state = run(7);
+        assert_eq!(post_state.bytes[..8], [0; 8]);
+        assert_eq!(post_state.bytes[8..12], (-1i32).to_le_bytes());
+
+        let out_of_bounds = build_module(
+            r#"
+            (module
+              (import "env" "eth2_hexEncode" (func $hexEncode (param i32 i32 i32 i32) (result i32)))
+              (memory (export "memory") 1)
+              (func (export "main")
+                (drop (call $hexEncode (i32.const 0) (i32.const 4) (i32.const 65530) (i32.const 8)))))
+            "#,
+        );
+        assert_eq!(
+            execute_code(
+                &out_of_bounds,
+                &Bytes32::default(),
+                &ShardBlockBody::default()
+            )
+            .unwrap_err(),
+            ExecError::MemoryAccessOutOfBounds
+        );
+    }
+
+    #[test]
+    fn storage_slot_count() {
+        let code = build_module(
+            r#"
+            (module
+              (import "env" "eth2_storageSlotCount" (func $storageSlotCount (result i32)))
+              (import "env" "eth2_savePostState" (func $savePostState (param i32)))
+              (memory (export "memory") 1)
+              (func (export "main")
+                (i32.store (i32.const 0) (call $storageSlotCount))
+                (call $savePostState (i32.const 0))))
+            "#,
+        );
+
+        let report = execute_code(&code, &Bytes32::default(), &ShardBlockBody::default()).unwrap();
+        assert_eq!(report.post_state.bytes[..4], [1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn assert_reports_message() {
+        let script = |condition: i32| {
+            build_module(&format!(
+                r#"
+                (module
+                  (import "env" "eth2_assert" (func $assert (param i32 i32 i32)))
+                  (memory (export "memory") 1)
+                  (data (i32.const 16) "balance underflow")
+                  (func (export "main")
+                    (call $assert (i32.const {}) (i32.const 16) (i32.const 17))))
+                "#,
+                condition
+            ))
+        };
+        let body = ShardBlockBody::default();
+
+        assert!(execute_code(&script(1), &Bytes32::default(), &body).is_ok());
+        let err = execute_code(&script(0), &Bytes32::default(), &body).unwrap_err();
+        assert_eq!(
+            err,
+            ExecError::AssertionFailed("balance underflow".to_string())
+        );
+    }
+
+    #[test]
+    fn read_write_u64_big_endian() {
+        // Reads the u64 at 0, writes it back incremented at 8 and saves both.
+        let code = build_module(
+            r#"
+            (module
+              (import "env" "eth2_readU64BE" (func $readU64BE (param i32) (result i64)))
+              (import "env" "eth2_writeU64BE" (func $writeU64BE (param i32 i64)))
+              (import "env" "eth2_savePostState" (func $savePostState (param i32)))
+              (memory (export "memory") 1)
+              (data (i32.const 0) "\01\02\03\04\05\06\07\08")
+              (func (export "main")
+                (call $writeU64BE (i32.const 8)
+                  (i64.add (call $readU64BE (i32.const 0)) (i64.const 0xf8)))
+                (call $writeU64BE (i32.const 16) (i64.const -1))
+                (call $savePostState (i32.const 0))))
+            "#,
+        );
+        let report = execute_code(&code, &Bytes32::default(), &ShardBlockBody::default()).unwrap();
+        assert_eq!(
+            report.post_state.bytes[..24],
+            [
+                1, 2, 3, 4, 5, 6, 7, 8, //
+                1, 2, 3, 4, 5, 6, 8, 0, //
+                0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            ]
+        );
+    }
+
+    #[test]
+    fn verify_merkle_proof() {
+        // Copies the leaf, a two node proof and the root from the block and checks the leaf
+        // at index 2, saving the result.
+        let code = build_module(
+            r#"
+            (module
+              (import "env" "eth2_blockDataCopy" (func $blockDataCopy (param i32 i32 i32)))
+              (import "env" "eth2_verifyMerkleProof" (func $verifyMerkleProof (param i32 i32 i32 i32 i32) (result i32)))
+              (import "env" "eth2_savePostState" (func $savePostState (param i32)))
+              (memory (export "memory") 1)
+              (func (export "main")
+                (call $blockDataCopy (i32.const 0) (i32.const 0) (i32.const 128))
+                (i32.store (i32.const 128)
+                  (call $verifyMerkleProof
+                    (i32.const 0) (i32.const 32) (i32.const 2) (i32.const 2) (i32.const 96)))
+                (call $savePostState (i32.const 128))))
+            "#,
+        );
+        let hash = |left: &[u8], right: &[u8]| {
+            Sha256::new()
+                .chain_update(left)
+                .chain_update(right)
+                .finalize()
+                .to_vec()
+        };
+        let leaves: Vec<Vec<u8>> = (0..4u8).map(|i| vec![i; 32]).collect();
+        let left = hash(&leaves[0], &leaves[1]);
+        let right = hash(&leaves[2], &leaves[3]);
+        let root = hash(&left, &right);
+        let run = |leaf: &[u8]| {
+            let data = [leaf, &leaves[3], &left, &root].concat();
+            execute_code(
+                &code,
+                &Bytes32::default(),
+                &ShardBlockBody { data: data.into() },
+            )
+            .unwrap()
+            .post_state
+            .bytes[0]
+        };
+
+        assert_eq!(run(&leaves[2]), 1);
+        assert_eq!(run(&leaves[1]), 0);
+    }
+
+    #[test]
+    fn code_size_and_copy() {
+        // Saves the code size followed by the first 4 bytes of the code.
+        let script = |copy_offset: u32| {
+            build_module(&format!(
+                r#"
+                (module
+                  (import "env" "eth2_codeSize" (func $codeSize (result i32)))
+                  (import "env" "eth2_codeCopy" (func $codeCopy (param i32 i32 i32)))
+                  (import "env" "eth2_savePostState" (func $savePostState (param i32)))
+                  (memory (export "memory") 1)
+                  (func (export "main")
+                    (i32.store (i32.const 0) (call $codeSize))
+                    (call $codeCopy (i32.const 4) (i32.const {}) (i32.const 4))
+                    (call $savePostState (i32.const 0))))
+                "#,
+                copy_offset
+            ))
+        };
+        let body = ShardBlockBody::default();
+
+        let code = script(0);
+        let report = execute_code(&code, &Bytes32::default(), &body).unwrap();
+        assert_eq!(
+            report.post_state.bytes[..4],
+            (code.len() as u32).to_le_bytes()
+        );
+        assert_eq!(&report.post_state.bytes[4..8], b"\0asm");
+
+        let code = script(1000);
+        let err = execute_code(&code, &Bytes32::default(), &body).unwrap_err();
+        assert_eq!(err, ExecError::CodeOutOfBounds);
+    }
+
+    #[test]
+    fn registered_host_function() {
+        let sha256: HostFn = Arc::new(|memory, args| {
+            let (ptr, length, out) = (arg_ptr(&args, 0), arg_len(&args, 1), arg_ptr(&args, 2));
+            let data = memory
+                .get(ptr, length as usize)
+                .map_err(|_| ExecError::MemoryAccessOutOfBounds)?;
+            memory
+                .set(out, &Sha256::digest(data))
+                .map_err(|_| ExecError::MemoryAccessOutOfBounds)?;
+            Ok(None)
+        });
+        let signature = || Signature::new(&[ValueType::I32; 3][..], None);
+        let mut host_functions = HostFunctions::default();
+        host_functions
+            .register_host_fn("eth2_sha256", signature(), sha256.clone())
+            .unwrap();
+
+        assert_eq!(
+            host_functions.register_host_fn("eth2_sha256", signature(), sha256.clone()),
+            Err(ScoutError::DuplicateHostFn("eth2_sha256".to_string()))
+        );
+        assert_eq!(
+            host_functions.register_host_fn("eth2_loadPreState", signature(), sha256),
+            Err(ScoutError::DuplicateHostFn("eth2_loadPreState".to_string()))
+        );
+
+        let code = build_module(
+            r#"
+            (module
+              (import "env" "eth2_sha256" (func $sha256 (param i32 i32 i32)))
+              (import "env" "eth2_savePostState" (func $savePostState (param i32)))
+              (memory (export "memory") 1)
+              (data (i32.const 0) "abc")
+              (func (export "main")
+                (call $sha256 (i32.const 0) (i32.const 3) (i32.const 32))
+                (call $savePostState (i32.const 32))))
+            "#,
+        );
+        let config = ExecutionConfig::default().with_host_functions(host_functions);
+        let report = execute_code_with_config(
+            &code,
+            &Bytes32::default(),
+            &ShardBlockBody::default(),
+            &config,
+        )
+        .unwrap();
+        assert_eq!(report.post_state.bytes[..], Sha256::digest(b"abc")[..]);
+        assert_eq!(report.host_calls["eth2_sha256"], 1);
+    }
+
+    #[test]
+    fn emit_log_collects_entries() {
+        let script = |topics_count: u32| {
+            build_module(&format!(
+                r#"
+                (module
+                  (import "env" "eth2_emitLog" (func $emitLog (param i32 i32 i32 i32)))
+                  (memory (export "memory") 1)
+                  (data (i32.const 0) "\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11")
+                  (data (i32.const 16) "\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11")
+                  (data (i32.const 200) "transfer")
+                  (func (export "main")
+                    (call $emitLog (i32.const 0) (i32.const {}) (i32.const 200) (i32.const 8))))
+                "#,
+                topics_count
+            ))
+        };
+        let body = ShardBlockBody::default();
+
+        let report = execute_code(&script(1), &Bytes32::default(), &body).unwrap();
+        assert_eq!(
+            report.logs,
+            vec![LogEntry {
+                topics: vec![Bytes32::from([0x11; 32])],
+                data: b"transfer".to_vec(),
+            }]
+        );
+
+        let err = execute_code(&script(5), &Bytes32::default(), &body).unwrap_err();
+        assert_eq!(err, ExecError::TooManyLogTopics(5));
+    }
+
+    #[test]
+    fn abort_rejects_block() {
+        let code = build_module(
+            r#"
+            (module
+              (import "env" "eth2_abort" (func $abort (param i32)))
+              (memory (export "memory") 1)
+              (func (export "main")
+                (call $abort (i32.const 42))))
+            "#,
+        );
+        let beacon_state = BeaconState {
+            execution_scripts: vec![ExecutionScript { code }],
+        };
+        let mut state = ShardState {
+            exec_env_states: vec![Bytes32::default()],
+            ..Default::default()
+        };
+        let err = process_shard_block(
+            &mut state,
+            &beacon_state,
+            Some(ShardBlock::default()),
+            &ExecutionConfig::default(),
+            &mut Metrics::default(),
+        )
+        .unwrap_err();
+        assert_eq!(err.source, ExecError::Aborted(42));
+        assert_eq!(state.slot, 0);
+    }
+
+    #[test]
+    fn transition_root_matches_simulation() {
+        let beacon_state = BeaconState {
+            execution_scripts: vec![ExecutionScript {
+                code: block_copy_script(0, 32),
+            }],
+        };
+        let state = ShardState {
+            exec_env_states: vec![Bytes32::default()],
+            ..Default::default()
+        };
+        let block = || ShardBlock {
+            env: 0,
+            data: ShardBlockBody {
+                data: vec![7; 32].into(),
+            },
+        };
+
+        let root = transition_root(&state, &beacon_state, Some(block())).unwrap();
+        let simulated = simulate_shard_block(
+            &state,
+            &beacon_state,
+            Some(block()),
+            &ExecutionConfig::default(),
+        )
+        .unwrap();
+        assert_eq!(root, simulated.state_root());
+        assert_eq!(simulated.exec_env_states[0].bytes, [7; 32]);
+        assert_eq!(state.slot, 0);
+        assert_eq!(state.exec_env_states[0], Bytes32::default());
+
+        let short_block = ShardBlock {
+            env: 0,
+            data: ShardBlockBody::default(),
+        };
+        assert_eq!(
+            transition_root(&state, &beacon_state, Some(short_block)),
+            Err(ExecError::BlockDataOutOfBounds)
+        );
+    }
+
+    #[test]
+    fn process_shard_blocks_policies() {
+        let beacon_state = BeaconState {
+            execution_scripts: vec![ExecutionScript {
+                code: block_copy_script(0, 32),
+            }],
+        };
+        let block = |data: Vec<u8>| ShardBlock {
+            env: 0,
+            data: ShardBlockBody { data: data.into() },
+        };
+        let blocks = || vec![block(vec![1; 32]), block(vec![2; 4]), block(vec![3; 32])];
+        let new_state = || ShardState {
+            exec_env_states: vec![Bytes32::default()],
+            ..Default::default()
+        };
+
+        let mut state = new_state();
+        let err = process_shard_blocks(
+            &mut state,
+            &beacon_state,
+            blocks().into_iter().map(Ok),
+            &ExecutionConfig::default(),
+            &mut Metrics::default(),
+            BatchPolicy::StopOnError,
+        )
+        .unwrap_err();
+        let err = match err {
+            ScoutError::BlockProcessing(err) => err,
+            err => panic!("unexpected error {}", err),
+        };
+        assert_eq!(err.slot, 1);
+        assert_eq!(err.source, ExecError::BlockDataOutOfBounds);
+        assert_eq!(state.slot, 1);
+        assert_eq!(state.exec_env_states[0].bytes, [1; 32]);
+
+        let mut state = new_state();
+        let failures = process_shard_blocks(
+            &mut state,
+            &beacon_state,
+            blocks().into_iter().map(Ok),
+            &ExecutionConfig::default(),
+            &mut Metrics::default(),
+            BatchPolicy::SkipAndContinue,
+        )
+        .unwrap();
+        assert_eq!(failures, vec![(1, ExecError::BlockDataOutOfBounds)]);
+        assert_eq!(state.slot, 2);
+        assert_eq!(state.exec_env_states[0].bytes, [3; 32]);
+    }
+
+    #[test]
+    fn unknown_env_fails_block() {
+        let beacon_state = BeaconState {
+            execution_scripts: vec![ExecutionScript {
+                code: block_copy_script(0, 32),
+            }],
+        };
+        let block = |env: u64| ShardBlock {
+            env,
+            data: ShardBlockBody {
+                data: vec![1; 32].into(),
+            },
+        };
+
+        let mut state = ShardState {
+            exec_env_states: vec![Bytes32::default()],
+            ..Default::default()
+        };
+        let failures = process_shard_blocks(
+            &mut state,
+            &beacon_state,
+            vec![block(5), block(u64::MAX), block(0)]
+                .into_iter()
+                .map(Ok),
+            &ExecutionConfig::default(),
+            &mut Metrics::default(),
+            BatchPolicy::SkipAndContinue,
+        )
+        .unwrap();
+        assert_eq!(
+            failures,
+            vec![
+                (0, ExecError::UnknownEnvironment(5)),
+                (1, ExecError::UnknownEnvironment(u64::MAX))
+            ]
+        );
+        assert_eq!(state.slot, 1);
+        assert_eq!(state.exec_env_states, vec![Bytes32::from([1; 32])]);
+    }
+
+    #[test]
+    fn rollback_preserves_trapped_env_state() {
+        let trap = build_module(
+            r#"
+            (module
+              (import "env" "eth2_savePostState" (func $savePostState (param i32)))
+              (memory (export "memory") 1)
+              (func (export "main")
+                (call $savePostState (i32.const 0))
+                unreachable))
+            "#,
+        );
+        let beacon_state = BeaconState {
+            execution_scripts: vec![
+                ExecutionScript {
+                    code: block_copy_script(0, 32),
+                },
+                ExecutionScript { code: trap },
+            ],
+        };
+        let block = |env: u64, byte: u8| ShardBlock {
+            env,
+            data: ShardBlockBody {
+                data: vec![byte; 32].into(),
+            },
+        };
+        let mut state = ShardState {
+            exec_env_states: vec![Bytes32::default(), Bytes32::from([9; 32])],
+            ..Default::default()
+        };
+
+        let failures = process_shard_blocks(
+            &mut state,
+            &beacon_state,
+            vec![block(0, 1), block(1, 2), block(0, 3)]
+                .into_iter()
+                .map(Ok),
+            &ExecutionConfig::default(),
+            &mut Metrics::default(),
+            BatchPolicy::RollbackAndContinue,
+        )
+        .unwrap();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, 1);
+        assert!(matches!(failures[0].1, ExecError::Trap(_)));
+        assert_eq!(state.slot, 3);
+        assert_eq!(
+            state.exec_env_states,
+            vec![Bytes32::from([3; 32]), Bytes32::from([9; 32])]
+        );
+    }
+
+    #[test]
+    fn empty_script_policy() {
+        let beacon_state = BeaconState {
+            execution_scripts: vec![ExecutionScript::default()],
+        };
+        let pre_state = Bytes32::from([9; 32]);
+        let process = |config: &ExecutionConfig| {
+            let mut state = ShardState {
+                exec_env_states: vec![pre_state],
+                ..Default::default()
+            };
+            process_shard_block(
+                &mut state,
+                &beacon_state,
+                Some(ShardBlock::default()),
+                config,
+                &mut Metrics::default(),
+            )
+            .map(|_| state)
+        };
+
+        let err = process(&ExecutionConfig::default()).unwrap_err();
+        assert_eq!(err.env, 0);
+        assert_eq!(err.source, ExecError::EmptyScript);
+
+        let state = process(&ExecutionConfig::default().with_skip_empty_scripts(true)).unwrap();
+        assert_eq!(state.exec_env_states, vec![pre_state]);
+        assert_eq!(state.slot, 1);
+    }
+
+    #[test]
+    fn epoch_boundary_hook() {
+        let beacon_state = BeaconState {
+            execution_scripts: vec![ExecutionScript {
+                code: block_copy_script(0, 0),
+            }],
+        };
+        let mut state = ShardState {
+            exec_env_states: vec![Bytes32::default()],
+            slot: SLOTS_PER_EPOCH - 2,
+            ..Default::default()
+        };
+        let mut epochs = Vec::new();
+
+        process_shard_blocks_with_epoch_hook(
+            &mut state,
+            &beacon_state,
+            vec![Ok(ShardBlock::default()); 4],
+            &ExecutionConfig::default(),
+            &mut Metrics::default(),
+            BatchPolicy::StopOnError,
+            &mut |epoch| epochs.push(epoch),
+        )
+        .unwrap();
+        assert_eq!(state.slot, SLOTS_PER_EPOCH + 2);
+        assert_eq!(epochs, vec![1]);
+    }
+
+    #[test]
+    fn process_shard_block_reports_changed_envs() {
+        let beacon_state = BeaconState {
+            execution_scripts: vec![
+                ExecutionScript {
+                    code: block_copy_script(0, 32),
+                };
+                2
+            ],
+        };
+        let mut state = ShardState {
+            exec_env_states: vec![Bytes32::default(); 2],
+            ..Default::default()
+        };
+        let block = |data: Vec<u8>| ShardBlock {
+            env: 1,
+            data: ShardBlockBody { data: data.into() },
+        };
+        let mut process = |block: Option<ShardBlock>| {
+            process_shard_block(
+                &mut state,
+                &beacon_state,
+                block,
+                &ExecutionConfig::default(),
+                &mut Metrics::default(),
+            )
+            .unwrap()
+        };
+
+        assert_eq!(process(Some(block(vec![1; 32]))), vec![1]);
+        // Saving the same state again changes nothing, neither does an empty slot.
+        assert_eq!(process(Some(block(vec![1; 32]))), Vec::<usize>::new());
+        assert_eq!(process(None), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn failing_block_leaves_state_untouched() {
+        let trap = build_module(
+            r#"
+            (module
+              (import "env" "eth2_savePostState" (func $savePostState (param i32)))
+              (import "env" "eth2_pushNewDeposit" (func $pushNewDeposit (param i32)))
+              (memory (export "memory") 1)
+              (func (export "main")
+                (call $savePostState (i32.const 0))
+                (call $pushNewDeposit (i32.const 0))
+                unreachable))
+            "#,
+        );
+        let beacon_state = BeaconState {
+            execution_scripts: vec![ExecutionScript::default(), ExecutionScript { code: trap }],
+        };
+        let mut state = ShardState {
+            exec_env_states: vec![Bytes32::from([9; 32])],
+            slot: 100,
+            latest_state_roots: vec![Bytes32::from([7; 32])],
+            ..Default::default()
+        };
+        let before = state.clone();
+        let block = ShardBlock {
+            env: 1,
+            data: ShardBlockBody {
+                data: vec![1; 32].into(),
+            },
+        };
+
+        let error = process_shard_block(
+            &mut state,
+            &beacon_state,
+            Some(block),
+            &ExecutionConfig::default(),
+            &mut Metrics::default(),
+        )
+        .unwrap_err();
+        assert!(matches!(error.source, ExecError::Trap(_)));
+        assert_eq!(state.exec_env_states, before.exec_env_states);
+        assert_eq!(state.slot, before.slot);
+        assert_eq!(state.parent_block, before.parent_block);
+        assert_eq!(state.latest_state_roots, before.latest_state_roots);
+    }
+
+    #[test]
+    fn snapshot_restores_state() {
+        let beacon_state = BeaconState {
+            execution_scripts: vec![ExecutionScript {
+                code: block_copy_script(0, 32),
+            }],
+        };
+        let mut state = ShardState {
+            exec_env_states: vec![Bytes32::from([9; 32])],
+            ..Default::default()
+        };
+        let block = ShardBlock {
+            env: 0,
+            data: ShardBlockBody {
+                data: vec![1; 32].into(),
+            },
+        };
+
+        let snapshot = state.snapshot();
+        let root = state.state_root();
+        process_shard_block(
+            &mut state,
+            &beacon_state,
+            Some(block),
+            &ExecutionConfig::default(),
+            &mut Metrics::default(),
+        )
+        .unwrap();
+        assert_eq!(state.slot, 1);
+        assert_ne!(state.state_root(), root);
+
+        state.restore(snapshot.clone());
+        assert_eq!(state.slot, snapshot.slot);
+        assert_eq!(state.exec_env_states, snapshot.exec_env_states);
+        assert_eq!(state.latest_state_roots, snapshot.latest_state_roots);
+        assert_eq!(state.state_root(), root);
+    }
+
+    #[test]
+    fn block_reader_matches_in_memory_blocks() {
+        let beacon_state = BeaconState {
+            execution_scripts: vec![ExecutionScript {
+                code: block_copy_script(0, 32),
+            }],
+        };
+        let blocks: Vec<_> = (1..=3)
+            .map(|byte| ShardBlock {
+                env: 0,
+                data: ShardBlockBody {
+                    data: vec![byte; 32].into(),
+                },
+            })
+            .collect();
+        let stream: Vec<u8> = blocks.iter().flat_map(ShardBlock::to_ssz).collect();
+        fn process<I>(
+            beacon_state: &BeaconState,
+            blocks: I,
+        ) -> (Result<Vec<(usize, ExecError)>, ScoutError>, Bytes32)
+        where
+            I: IntoIterator<Item = Result<ShardBlock, ScoutError>>,
+        {
+            let mut state = ShardState {
+                exec_env_states: vec![Bytes32::default()],
+                ..Default::default()
+            };
+            let failures = process_shard_blocks(
+                &mut state,
+                beacon_state,
+                blocks,
+                &ExecutionConfig::default(),
+                &mut Metrics::default(),
+                BatchPolicy::StopOnError,
+            );
+            (failures, state.state_root())
+        }
+
+        let in_memory = process(&beacon_state, blocks.into_iter().map(Ok));
+        let streamed = process(&beacon_state, BlockReader::new(&stream[..]));
+        assert_eq!(streamed, in_memory);
+        assert_eq!(streamed.0, Ok(vec![]));
+
+        let mut truncated = BlockReader::new(&stream[..stream.len() - 1]);
+        assert!(truncated.next().unwrap().is_ok());
+        assert!(truncated.next().unwrap().is_ok());
+        assert!(matches!(
+            truncated.next(),
+            Some(Err(ScoutError::MalformedSsz(_)))
+        ));
+        assert!(truncated.next().is_none());
+    }
+
+    #[test]
+    fn seeded_random_is_deterministic() {
+        // Fills 40 bytes, leaving the second word of post-state to the second stream block.
+        let code = build_module(
+            r#"
+            (module
+              (import "env" "eth2_seededRandom" (func $seededRandom (param i32 i32)))
+              (import "env" "eth2_savePostState" (func $savePostState (param i32)))
+              (memory (export "memory") 1)
+              (func (export "main")
+                (call $seededRandom (i32.const 0) (i32.const 40))
+                (call $savePostState (i32.const 8))))
+            "#,
+        );
+        let pre_state = Bytes32::from([7u8; 32]);
+        let run = |slot| {
+            let config = ExecutionConfig::default().with_slot(slot);
+            execute_code_with_config(&code, &pre_state, &ShardBlockBody::default(), &config)
+                .unwrap()
+                .post_state
+        };
+
+        assert_eq!(run(3), run(3));
+        assert_ne!(run(3), run(4));
+        assert_eq!(
+            run(3).bytes[..],
+            seeded_random_bytes(&pre_state, 3, 40)[8..]
+        );
+    }
+
+    #[test]
+    fn seeded_random_out_of_bounds() {
+        let code = build_module(
+            r#"
+            (module
+              (import "env" "eth2_seededRandom" (func $seededRandom (param i32 i32)))
+              (memory (export "memory") 1)
+              (func (export "main")
+                (call $seededRandom (i32.const 65530) (i32.const -1))))
+            "#,
+        );
+        let err = execute_code(&code, &Bytes32::default(), &ShardBlockBody::default()).unwrap_err();
+        assert_eq!(err, ExecError::MemoryAccessOutOfBounds);
+    }
+
+    #[test]
+    fn host_calls_are_logged_with_fields() {
+        use std::sync::Mutex;
+        use tracing::field::{Field, Visit};
+        use tracing_subscriber::layer::{Context, SubscriberExt};
+        use tracing_subscriber::Layer;
+
+        #[derive(Default)]
+        struct Fields(BTreeMap<String, String>);
+
+        #[derive(Default)]
+        struct Capture(Arc<Mutex<Vec<BTreeMap<String, String>>>>);
+
+        impl Visit for Fields {
+            fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+                self.0
+                    .insert(field.name().to_string(), format!("{:?}", value));
+            }
+        }
+
+        impl<S: tracing::Subscriber> Layer<S> for Capture {
+            fn on_event(&self, event: &tracing::Event, _ctx: Context<S>) {
+                let mut fields = Fields::default();
+                event.record(&mut fields);
+                self.0.lock().unwrap().push(fields.0);
+            }
+        }
+
+        let capture = Capture::default();
+        let events = capture.0.clone();
+        let subscriber = tracing_subscriber::registry().with(capture);
+        tracing::subscriber::with_default(subscriber, || {
+            let body = ShardBlockBody {
+                data: vec![0; 40].into(),
+            };
+            execute_code(&block_copy_script(4, 32), &Bytes32::default(), &body).unwrap();
+        });
+
+        let events = events.lock().unwrap();
+        let copy = events
+            .iter()
+            .find(|fields| fields["message"] == "eth2_blockDataCopy")
+            .expect("blockDataCopy event");
+        assert_eq!(copy["ptr"], "0");
+        assert_eq!(copy["offset"], "4");
+        assert_eq!(copy["length"], "32");
+    }
+
+    #[test]
+    fn revert_on_error_restores_state_exactly() {
+        let trap = trap_module();
+        let beacon_state = BeaconState {
+            execution_scripts: vec![
+                ExecutionScript {
+                    code: block_copy_script(0, 32),
+                },
+                ExecutionScript { code: trap },
+                ExecutionScript {
+                    code: block_copy_script(0, 32),
+                },
+            ],
+        };
+        // A full set of recent roots, so advancing the slot evicts the oldest one.
+        let before = ShardState {
+            exec_env_states: vec![Bytes32::from([9; 32])],
+            slot: 100,
+            latest_state_roots: (0..LATEST_STATE_ROOTS_LENGTH)
+                .map(|i| Bytes32::from([i as u8; 32]))
+                .collect(),
+            ..Default::default()
+        };
+        let block = |env: u64| {
+            Ok(ShardBlock {
+                env,
+                data: ShardBlockBody {
+                    data: vec![1; 32].into(),
+                },
+            })
+        };
+        let run = |policy: BatchPolicy| {
+            let mut state = before.clone();
+            // The first block overwrites a root, the second grows the store.
+            let result = process_shard_blocks(
+                &mut state,
+                &beacon_state,
+                vec![block(0), block(2), block(1)],
+                &ExecutionConfig::default(),
+                &mut Metrics::default(),
+                policy,
+            );
+            assert!(matches!(
+                result,
+                Err(ScoutError::BlockProcessing(BlockProcessingError {
+                    slot: 102,
+                    ..
+                }))
+            ));
+            state
+        };
+
+        let state = run(BatchPolicy::RevertOnError);
+        assert_eq!(state.exec_env_states, before.exec_env_states);
+        assert_eq!(state.slot, before.slot);
+        assert_eq!(state.parent_block, before.parent_block);
+        assert_eq!(state.latest_state_roots, before.latest_state_roots);
+
+        let mut state = before.clone();
+        let mut journal = ShardStateJournal::default();
+        for block in [Some(block(2).unwrap()), None] {
+            process_shard_block_journaled(
+                &mut state,
+                &beacon_state,
+                block,
+                &ExecutionConfig::default(),
+                &mut Metrics::default(),
+                &mut journal,
+            )
+            .unwrap();
+        }
+        journal.undo(&mut state);
+        assert_eq!(state.exec_env_states, before.exec_env_states);
+        assert_eq!(state.slot, before.slot);
+        assert_eq!(state.state_root(), before.state_root());
+
+        // Without the journal the blocks before the failing one stay applied.
+        let state = run(BatchPolicy::StopOnError);
+        assert_eq!(state.exec_env_states.len(), 3);
+        assert_eq!(state.slot, before.slot + 2);
+    }
+
+    #[test]
+    fn env_state_stores_behave_alike() {
+        /// Keeps only the roots which were set. Deliberately not `Clone`, like a store backed
+        /// by a database.
+        #[derive(Default, Debug)]
+        struct MapStore {
+            roots: std::collections::HashMap<usize, Bytes32>,
+            len: usize,
+        }
+
+        impl EnvStateStore for MapStore {
+            fn get(&self, env: usize) -> Bytes32 {
+                self.roots.get(&env).copied().unwrap_or(ZERO_HASH)
+            }
+
+            fn set(&mut self, env: usize, root: Bytes32) {
+                self.roots.insert(env, root);
+                self.len = self.len.max(env + 1);
+            }
+
+            fn len(&self) -> usize {
+                self.len
+            }
+
+            fn truncate(&mut self, len: usize) {
+                self.roots.retain(|&env, _| env < len);
+                self.len = self.len.min(len);
+            }
+        }
+
+        fn run<S: EnvStateStore + Default>() -> (Vec<Bytes32>, Vec<Bytes32>) {
+            let beacon_state = BeaconState {
+                execution_scripts: vec![
+                    ExecutionScript {
+                        code: block_copy_script(0, 32),
+                    };
+                    3
+                ],
+            };
+            let mut state = ShardState::<S>::default();
+            let mut roots = Vec::new();
+            for (env, byte) in [(0, 1), (2, 2), (0, 3)] {
+                let block = ShardBlock {
+                    env,
+                    data: ShardBlockBody {
+                        data: vec![byte; 32].into(),
+                    },
+                };
+                process_shard_block(
+                    &mut state,
+                    &beacon_state,
+                    Some(block),
+                    &ExecutionConfig::default(),
+                    &mut Metrics::default(),
+                )
+                .unwrap();
+                roots.push(state.state_root());
+            }
+            let states = (0..state.exec_env_states.len())
+                .map(|env| state.exec_env_states.get(env))
+                .collect();
+            (states, roots)
+        }
+
+        let (states, roots) = run::<Vec<Bytes32>>();
+        assert_eq!(
+            states,
+            [Bytes32::from([3; 32]), ZERO_HASH, Bytes32::from([2; 32])]
+        );
+        assert_eq!(run::<MapStore>(), (states, roots));
+    }
+
+    #[test]
+    fn block_data_copy_reversed_range() {
+        let body = ShardBlockBody {
+            data: vec![0; 16].into(),
+        };
+        let run = |offset, length| {
+            execute_code(
+                &block_copy_script(offset, length),
+                &Bytes32::default(),
+                &body,
+            )
+            .map(|_| ())
+        };
+
+        assert_eq!(
+            run(8, u32::MAX),
+            Err(ExecError::ReversedBlockDataRange(8, 7))
+        );
+        assert_eq!(run(8, 100), Err(ExecError::BlockDataOutOfBounds));
+        assert_eq!(run(8, 8), Ok(()));
+    }
+
+    #[test]
+    fn block_data_copy_uses_offset_and_length() {
+        let block_data = ShardBlockBody {
+            data: (0u8..8).collect(),
+        };
+        let code = block_copy_script(4, 4);
+        let report = execute_code(&code, &Bytes32::default(), &block_data).unwrap();
+        assert_eq!(report.post_state.bytes[..5], [4, 5, 6, 7, 0]);
+
+        let code = block_copy_script(6, 4);
+        let result = execute_code(&code, &Bytes32::default(), &block_data);
+        assert_eq!(result.unwrap_err(), ExecError::BlockDataOutOfBounds);
+    }
+
+    #[test]
+    fn multiple_memories_rejected() {
+        let code = build_module(
+            r#"
+            (module
+              (memory (export "memory") 1)
+              (memory (export "scratch") 1)
+              (func (export "main")))
+            "#,
+        );
+        let err = execute_code(&code, &Bytes32::default(), &ShardBlockBody::default()).unwrap_err();
+        assert_eq!(err, ExecError::MultipleMemories);
+    }
+
+    #[test]
+    fn reject_float() {
+        let code = build_module(
+            r#"
+            (module
+              (memory (export "memory") 1)
+              (func (export "main")
+                (drop (f64.add (f64.const 1) (f64.const 2)))))
+            "#,
+        );
+        let body = ShardBlockBody::default();
+
+        assert!(execute_code(&code, &Bytes32::default(), &body).is_ok());
+        let config = ExecutionConfig::default().with_reject_float(true);
+        let err = execute_code_with_config(&code, &Bytes32::default(), &body, &config).unwrap_err();
+        assert_eq!(err, ExecError::FloatingPointForbidden);
+    }
+
+    #[test]
+    fn strict_import_namespace() {
+        let script = |namespace: &str| {
+            build_module(&format!(
+                r#"
+                (module
+                  (import "{}" "eth2_savePostState" (func $savePostState (param i32)))
+                  (memory (export "memory") 1)
+                  (func (export "main")
+                    (call $savePostState (i32.const 0))))
+                "#,
+                namespace
+            ))
+        };
+        let body = ShardBlockBody::default();
+        let config = ExecutionConfig::default().with_strict_import_namespace(true);
+
+        assert!(execute_code(&script("env"), &Bytes32::default(), &body).is_ok());
+        assert!(execute_code(&script("eth2"), &Bytes32::default(), &body).is_ok());
+        assert!(
+            execute_code_with_config(&script("eth2"), &Bytes32::default(), &body, &config).is_ok()
+        );
+        let err = execute_code_with_config(&script("env"), &Bytes32::default(), &body, &config)
+            .unwrap_err();
+        assert_eq!(err, ExecError::UnexpectedImportModule("env".to_string()));
+
+        let err = execute_code(&script("ethereum"), &Bytes32::default(), &body).unwrap_err();
+        assert!(
+            matches!(err, ExecError::Instantiation(ref message) if message.contains("ethereum")),
+            "{}",
+            err
+        );
+    }
+
+    /// Compares linking a module through the resolver with the `ImportsBuilder` it replaced. Run
+    /// with `cargo test --release -- --ignored import_resolution_overhead --nocapture`.
+    #[test]
+    #[ignore]
+    fn import_resolution_overhead() {
+        use wasmi::ImportsBuilder;
+
+        const ROUNDS: u32 = 20_000;
+        const BATCHES: usize = 20;
+        let config = ExecutionConfig::default();
+        let (module, ..) = wasm_load_from_blob(&block_copy_script(0, 32), &config).unwrap();
+        let resolver = RuntimeModuleImportResolver;
+        let time = |link: &dyn Fn()| {
+            let started = Instant::now();
+            for _ in 0..ROUNDS {
+                link();
+            }
+            started.elapsed() / ROUNDS
+        };
+        let with_builder = || {
+            let imports = ImportsBuilder::new()
+                .with_resolver("env", &resolver)
+                .with_resolver("eth2", &resolver);
+            ModuleInstance::new(&module, &imports).unwrap();
+        };
+        let with_resolver = || {
+            ModuleInstance::new(&module, &resolver).unwrap();
+        };
+
+        // The fastest of interleaved batches, to keep out noise from the rest of the machine.
+        let (mut builder, mut direct) = (Duration::MAX, Duration::MAX);
+        for _ in 0..BATCHES {
+            builder = builder.min(time(&with_builder));
+            direct = direct.min(time(&with_resolver));
+        }
+        eprintln!(
+            "per instantiation: {:?} with ImportsBuilder, {:?} with the resolver",
+            builder, direct
+        );
+    }
+
+    #[test]
+    fn reject_trailing_bytes() {
+        let code = loadstore_module();
+        let body = ShardBlockBody::default();
+        let config = ExecutionConfig::default().with_reject_trailing_bytes(true);
+        let run = |code: &[u8]| {
+            execute_code_with_config(code, &Bytes32::default(), &body, &config).map(|_| ())
+        };
+
+        assert_eq!(run(&code), Ok(()));
+        // A custom section is part of the module, wherever it is.
+        assert_eq!(run(&[&code[..], &[0, 3, 2, b'h', b'i']].concat()), Ok(()));
+        // Junk which is not a section, and a type section claiming more bytes than are left.
+        let junk = [&code[..], &[0xde, 0xad, 0xbe, 0xef]].concat();
+        let truncated = [&code[..], &[1, 16, 0]].concat();
+        assert_eq!(run(&junk), Err(ExecError::TrailingBytes(code.len())));
+        assert_eq!(run(&truncated), Err(ExecError::TrailingBytes(code.len())));
+        assert!(matches!(
+            execute_code(&junk, &Bytes32::default(), &body),
+            Err(ExecError::InvalidModule(_))
+        ));
+    }
+
+    #[test]
+    fn entry_point_fallback() {
+        let code = build_module(
+            r#"
+            (module
+              (import "env" "eth2_savePostState" (func $savePostState (param i32)))
+              (memory (export "memory") 1)
+              (data (i32.const 0) "\2a")
+              (func (export "_start")
+                (call $savePostState (i32.const 0))))
+            "#,
+        );
+        let body = ShardBlockBody::default();
+
+        let err = execute_code(&code, &Bytes32::default(), &body).unwrap_err();
+        assert_eq!(err, ExecError::MissingExport("main".to_string()));
+
+        let config = ExecutionConfig::default().with_entry_points(vec!["main", "_start"]);
+        let report = execute_code_with_config(&code, &Bytes32::default(), &body, &config).unwrap();
+        assert_eq!(report.post_state.bytes[0], 0x2a);
+    }
+
+    #[test]
+    fn gas_global_reports_consumed_gas() {
+        let code = build_module(
+            r#"
+            (module
+              (memory (export "memory") 1)
+              (global $gas (export "gas") (mut i64) (i64.const 1000))
+              (func (export "main")
+                (global.set $gas (i64.sub (global.get $gas) (i64.const 250)))))
+            "#,
+        );
+        let report = execute_code(&code, &Bytes32::default(), &ShardBlockBody::default()).unwrap();
+        assert_eq!(report.gas_used, 250);
+
+        // Without the global the instruction counter is reported.
+        let code = loadstore_module();
+        let report = execute_code(&code, &Bytes32::default(), &ShardBlockBody::default()).unwrap();
+        assert!(report.gas_used > 0);
+    }
+
+    #[test]
+    fn call_stack_limit() {
+        // Recurses to the depth given by the first byte of the block.
+        let code = build_module(
+            r#"
+            (module
+              (import "env" "eth2_blockDataCopy" (func $blockDataCopy (param i32 i32 i32)))
+              (memory (export "memory") 1)
+              (func $recurse (param $depth i32)
+                (if (local.get $depth)
+                  (then (call $recurse (i32.sub (local.get $depth) (i32.const 1))))))
+              (func (export "main")
+                (call $blockDataCopy (i32.const 0) (i32.const 0) (i32.const 1))
+                (call $recurse (i32.load8_u (i32.const 0)))))
+            "#,
+        );
+        let body = ShardBlockBody {
+            data: vec![100].into(),
+        };
+        let run = |config: &ExecutionConfig| {
+            execute_code_with_config(&code, &Bytes32::default(), &body, config).map(|_| ())
+        };
+
+        assert_eq!(run(&ExecutionConfig::default()), Ok(()));
+        let config = ExecutionConfig::default().with_stack_limits(VALUE_STACK_LIMIT, 50);
+        assert_eq!(run(&config), Err(ExecError::CallDepthExceeded));
+        // Main calls the function once, which then recurses 100 times.
+        let config = ExecutionConfig::default().with_stack_limits(VALUE_STACK_LIMIT, 101);
+        assert_eq!(run(&config), Ok(()));
+        let config = ExecutionConfig::default().with_stack_limits(VALUE_STACK_LIMIT, 100);
+        assert_eq!(run(&config), Err(ExecError::CallDepthExceeded));
+    }
+
+    #[test]
+    fn value_stack_limit() {
+        // A single function nesting additions 512 deep, without any calls.
+        let depth = 512;
+        let code = build_module(&format!(
+            r#"
+            (module
+              (memory (export "memory") 1)
+              (func (export "main")
+                {}
+                {}
+                drop))
+            "#,
+            "(i32.const 1) ".repeat(depth),
+            "i32.add ".repeat(depth - 1)
+        ));
+        let run = |config: &ExecutionConfig| {
+            execute_code_with_config(
+                &code,
+                &Bytes32::default(),
+                &ShardBlockBody::default(),
+                config,
+            )
+            .map(|_| ())
+        };
+
+        assert_eq!(run(&ExecutionConfig::default()), Ok(()));
+        let config = ExecutionConfig::default().with_stack_limits(1024, CALL_STACK_LIMIT);
+        assert_eq!(run(&config), Err(ExecError::StackOverflow));
+    }
+
+    #[test]
+    fn capture_diagnostics() {
+        let code = build_module(
+            r#"
+            (module
+              (memory (export "memory") 1)
+              (func (export "main")
+                (drop (i32.div_u (i32.const 1) (i32.const 0)))))
+            "#,
+        );
+        let run = |config: &ExecutionConfig| {
+            execute_code_with_config(
+                &code,
+                &Bytes32::default(),
+                &ShardBlockBody::default(),
+                config,
+            )
+            .unwrap_err()
+        };
+
+        assert!(matches!(
+            run(&ExecutionConfig::default()),
+            ExecError::Trap(_)
+        ));
+        match run(&ExecutionConfig::default().with_diagnostics(true)) {
+            ExecError::Diagnosed(error, diagnostic) => {
+                assert!(matches!(*error, ExecError::Trap(_)));
+                assert_eq!(diagnostic.trap_kind.as_deref(), Some("DivisionByZero"));
+                assert!(diagnostic.detail.contains("DivisionByZero"));
+            }
+            other => panic!("expected a diagnosed error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn host_call_limit() {
+        let code = build_module(
+            r#"
+            (module
+              (import "env" "eth2_blockDataSize" (func $blockDataSize (result i32)))
+              (memory (export "memory") 1)
+              (func (export "main")
+                (local $i i32)
+                (loop $continue
+                  (drop (call $blockDataSize))
+                  (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                  (br_if $continue (i32.lt_u (local.get $i) (i32.const 10))))))
+            "#,
+        );
+        let run = |config: &ExecutionConfig| {
+            execute_code_with_config(
+                &code,
+                &Bytes32::default(),
+                &ShardBlockBody::default(),
+                config,
+            )
+        };
+
+        assert!(run(&ExecutionConfig::default()).is_ok());
+        assert!(run(&ExecutionConfig::default().with_max_host_calls(10)).is_ok());
+        let err = run(&ExecutionConfig::default().with_max_host_calls(9)).unwrap_err();
+        assert_eq!(err, ExecError::HostCallLimitExceeded);
+    }
+
+    #[test]
+    fn gas_limit() {
+        let code = build_module(
+            r#"
+            (module
+              (memory (export "memory") 1)
+              (func (export "main")
+                (local $i i32)
+                (loop $continue
+                  (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                  (br_if $continue (i32.lt_u (local.get $i) (i32.const 10))))))
+            "#,
+        );
+        let run = |config: &ExecutionConfig| {
+            execute_code_with_config(
+                &code,
+                &Bytes32::default(),
+                &ShardBlockBody::default(),
+                config,
+            )
+        };
+
+        let gas_used = run(&ExecutionConfig::default()).unwrap().gas_used;
+        assert!(run(&ExecutionConfig::default().with_gas_limit(gas_used)).is_ok());
+        let err = run(&ExecutionConfig::default().with_gas_limit(gas_used - 1)).unwrap_err();
+        assert_eq!(err, ExecError::OutOfGas);
+    }
+
+    #[test]
+    fn gas_left() {
+        let code = gas_left_module();
+        let run = |config: &ExecutionConfig| {
+            let report = execute_code_with_config(
+                &code,
+                &Bytes32::default(),
+                &ShardBlockBody::default(),
+                config,
+            )
+            .unwrap();
+            let mut gas_left = [0u8; 8];
+            gas_left.copy_from_slice(&report.post_state.bytes[..8]);
+            (i64::from_le_bytes(gas_left), report.gas_used)
+        };
+
+        let (gas_left, gas_used) = run(&ExecutionConfig::default().with_gas_limit(1000));
+        assert_eq!(gas_left, 1000 - gas_used as i64);
+        assert_eq!(run(&ExecutionConfig::default()).0, i64::MAX);
+    }
+
+    #[test]
+    fn save_post_state_from() {
+        let run = |length: u32| {
+            let code = build_module(&format!(
+                r#"
+                (module
+                  (import "env" "eth2_savePostStateFrom" (func $savePostStateFrom (param i32 i32)))
+                  (memory (export "memory") 1)
+                  (data (i32.const 0) "a post-state of exactly 32 bytes")
+                  (func (export "main")
+                    (call $savePostStateFrom (i32.const 0) (i32.const {}))))
+                "#,
+                length
+            ));
+            let config = ExecutionConfig::default().with_require_post_state(true);
+            execute_code_with_config(
+                &code,
+                &Bytes32::default(),
+                &ShardBlockBody::default(),
+                &config,
+            )
+            .map(|report| report.post_state)
+        };
+
+        assert_eq!(&run(32).unwrap().bytes, b"a post-state of exactly 32 bytes");
+        assert_eq!(run(16), Err(ExecError::BadPostStateLength(16)));
+        assert_eq!(run(33), Err(ExecError::BadPostStateLength(33)));
+    }
+
+    #[test]
+    fn untouched_memory_reads_as_zero() {
+        // Saves from a region of the initial page and from a page grown at runtime, neither
+        // of which the script writes.
+        let script = |ptr: u32| {
+            build_module(&format!(
+                r#"
+                (module
+                  (import "env" "eth2_savePostState" (func $savePostState (param i32)))
+                  (memory (export "memory") 1 2)
+                  (data (i32.const 0) "initialized")
+                  (func (export "main")
+                    (drop (memory.grow (i32.const 1)))
+                    (call $savePostState (i32.const {}))))
+                "#,
+                ptr
+            ))
+        };
+        let pre_state = Bytes32::from([0xff; 32]);
+        let config = ExecutionConfig::default().with_require_post_state(true);
+        for ptr in [1024, 65536] {
+            let report = execute_code_with_config(
+                &script(ptr),
+                &pre_state,
+                &ShardBlockBody::default(),
+                &config,
+            )
+            .unwrap();
+            assert_eq!(report.post_state, Bytes32::default());
+        }
+    }
+
+    #[test]
+    fn peak_memory_pages() {
+        let code = build_module(
+            r#"
+            (module
+              (memory (export "memory") 1)
+              (func (export "main")
+                (drop (memory.grow (i32.const 2)))))
+            "#,
+        );
+        let report = execute_code(&code, &Bytes32::default(), &ShardBlockBody::default()).unwrap();
+        assert_eq!(report.peak_memory_pages, 3);
+
+        let report = execute_code(
+            &loadstore_module(),
+            &Bytes32::default(),
+            &ShardBlockBody::default(),
+        )
+        .unwrap();
+        assert_eq!(report.peak_memory_pages, 1);
+    }
+
+    #[test]
+    fn initial_memory() {
+        let code = build_module(
+            r#"
+            (module
+              (import "env" "eth2_savePostState" (func $savePostState (param i32)))
+              (memory (export "memory") 1)
+              (func (export "main")
+                (call $savePostState (i32.const 16))))
+            "#,
+        );
+        let run = |initial_memory: Vec<u8>| {
+            let config = ExecutionConfig::default().with_initial_memory(initial_memory);
+            execute_code_with_config(
+                &code,
+                &Bytes32::default(),
+                &ShardBlockBody::default(),
+                &config,
+            )
+            .map(|report| report.post_state)
+        };
+
+        let pattern: Vec<u8> = (0..64).collect();
+        let expected: Vec<u8> = (16..48).collect();
+        assert_eq!(run(pattern).unwrap().bytes[..], expected[..]);
+        assert_eq!(
+            run(vec![1; WASM_PAGE_SIZE + 1]),
+            Err(ExecError::MemoryAccessOutOfBounds)
+        );
+    }
+
+    #[test]
+    fn run_start() {
+        let code = build_module(
+            r#"
+            (module
+              (import "env" "eth2_savePostState" (func $savePostState (param i32)))
+              (memory (export "memory") 1)
+              (func $init
+                (i64.store (i32.const 0) (i64.const 0x0807060504030201)))
+              (start $init)
+              (func (export "main")
+                (call $savePostState (i32.const 0))))
+            "#,
+        );
+        let run = |config: &ExecutionConfig| {
+            execute_code_with_config(
+                &code,
+                &Bytes32::default(),
+                &ShardBlockBody::default(),
+                config,
+            )
+            .map(|report| report.post_state)
+        };
+
+        assert_eq!(
+            run(&ExecutionConfig::default()),
+            Err(ExecError::UnexpectedStart)
+        );
+        let mut expected = [0u8; 32];
+        expected[..8].copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(
+            run(&ExecutionConfig::default().with_run_start(true)),
+            Ok(Bytes32::from(expected))
+        );
+    }
+
+    #[test]
+    fn require_post_state() {
+        let code = empty_main_module();
+        let body = ShardBlockBody::default();
+
+        let report = execute_code(&code, &Bytes32::default(), &body).unwrap();
+        assert_eq!(report.post_state, Bytes32::default());
+
+        let config = ExecutionConfig::default().with_require_post_state(true);
+        let err = execute_code_with_config(&code, &Bytes32::default(), &body, &config).unwrap_err();
+        assert_eq!(err, ExecError::NoPostState);
+    }
+
+    #[test]
+    fn single_post_state() {
+        let code = build_module(
+            r#"
+            (module
+              (import "env" "eth2_savePostState" (func $savePostState (param i32)))
+              (import "env" "eth2_savePostStateFrom" (func $savePostStateFrom (param i32 i32)))
+              (memory (export "memory") 1)
+              (data (i32.const 32) "\01")
+              (func (export "main")
+                (call $savePostState (i32.const 0))
+                (call $savePostStateFrom (i32.const 32) (i32.const 32))))
+            "#,
+        );
+        let body = ShardBlockBody::default();
+
+        let report = execute_code(&code, &Bytes32::default(), &body).unwrap();
+        assert_eq!(report.post_state.bytes[0], 1);
+        let config = ExecutionConfig::default().with_single_post_state(true);
+        assert_eq!(
+            execute_code_with_config(&code, &Bytes32::default(), &body, &config).unwrap_err(),
+            ExecError::MultiplePostStates
+        );
+        let once = loadstore_module();
+        assert!(execute_code_with_config(&once, &Bytes32::default(), &body, &config).is_ok());
+    }
+
+    #[test]
+    fn unsaved_post_state_keeps_pre_state() {
+        let no_save = empty_main_module();
+        let save_zeros = build_module(
+            r#"
+            (module
+              (import "env" "eth2_savePostState" (func $savePostState (param i32)))
+              (memory (export "memory") 1)
+              (func (export "main")
+                (call $savePostState (i32.const 0))))
+            "#,
+        );
+        let pre_state = Bytes32::from([7; 32]);
+        let body = ShardBlockBody::default();
+
+        let report = execute_code(&no_save, &pre_state, &body).unwrap();
+        assert_eq!(report.post_state, pre_state);
+        let report = execute_code(&save_zeros, &pre_state, &body).unwrap();
+        assert_eq!(report.post_state, Bytes32::default());
+    }
+
+    #[test]
+    fn block_data_copy_overflow() {
+        // Copies 8 bytes from offset 4 of a 6 byte body, saving the returned length after them.
+        let code = build_module(
+            r#"
+            (module
+              (import "env" "eth2_blockDataCopy" (func $blockDataCopy (param i32 i32 i32) (result i32)))
+              (import "env" "eth2_savePostState" (func $savePostState (param i32)))
+              (memory (export "memory") 1)
+              (func (export "main")
+                (i32.store8 (i32.const 8)
+                  (call $blockDataCopy (i32.const 0) (i32.const 4) (i32.const 8)))
+                (call $savePostState (i32.const 0))))
+            "#,
+        );
+        let body = ShardBlockBody {
+            data: vec![1, 2, 3, 4, 5, 6].into(),
+        };
+
+        let err = execute_code(&code, &Bytes32::default(), &body).unwrap_err();
+        assert_eq!(err, ExecError::BlockDataOutOfBounds);
+
+        let config = ExecutionConfig::default().with_copy_overflow(CopyOverflow::Clamp);
+        let report = execute_code_with_config(&code, &Bytes32::default(), &body, &config).unwrap();
+        assert_eq!(report.post_state.bytes[..9], [5, 6, 0, 0, 0, 0, 0, 0, 2]);
+        assert_eq!(report.host_calls["eth2_blockDataCopy"], 1);
+    }
+
+    #[test]
+    fn block_data_copy_from_empty_body() {
+        let body = ShardBlockBody::default();
+        let strict = ExecutionConfig::default().with_reject_unconsumed_data(true);
+
+        let report = execute_code_with_config(
+            &block_copy_script(0, 0),
+            &Bytes32::default(),
+            &body,
+            &strict,
+        )
+        .unwrap();
+        assert_eq!(report.post_state, Bytes32::default());
+        assert_eq!(
+            execute_code(&block_copy_script(0, 1), &Bytes32::default(), &body).unwrap_err(),
+            ExecError::BlockDataOutOfBounds
+        );
+        assert_eq!(
+            execute_code(&block_copy_script(1, 0), &Bytes32::default(), &body).unwrap_err(),
+            ExecError::BlockDataOutOfBounds
+        );
+    }
+
+    #[test]
+    fn reject_unconsumed_block_data() {
+        let block_data = ShardBlockBody {
+            data: vec![1; 8].into(),
+        };
+        let half = block_copy_script(0, 4);
+        let all = block_copy_script(0, 8);
+        let config = ExecutionConfig::default().with_reject_unconsumed_data(true);
+
+        let result = execute_code_with_config(&half, &Bytes32::default(), &block_data, &config);
+        assert_eq!(result.unwrap_err(), ExecError::UnconsumedBlockData);
+        assert!(execute_code_with_config(&all, &Bytes32::default(), &block_data, &config).is_ok());
+        assert!(execute_code(&half, &Bytes32::default(), &block_data).is_ok());
+    }
+
+    #[test]
+    fn reject_unsupported_features() {
+        let simd = build_module(
+            r#"
+            (module
+              (memory (export "memory") 1)
+              (func (export "main")
+                (drop (v128.const i32x4 0 0 0 0))))
+            "#,
+        );
+        let result = execute_code(&simd, &Bytes32::default(), &ShardBlockBody::default());
+        assert_eq!(
+            result.unwrap_err(),
+            ExecError::UnsupportedFeature("simd".into())
+        );
+
+        let sign_ext = build_module(
+            r#"
+            (module
+              (memory (export "memory") 1)
+              (func (export "main")
+                (drop (i32.extend8_s (i32.const 1)))))
+            "#,
+        );
+        let result = execute_code(&sign_ext, &Bytes32::default(), &ShardBlockBody::default());
+        assert_eq!(
+            result.unwrap_err(),
+            ExecError::UnsupportedFeature("sign-extension".into())
+        );
+    }
+
+    #[test]
+    fn timestamp_derived_from_slot() {
+        let code = build_module(
+            r#"
+            (module
+              (import "env" "eth2_timestamp" (func $timestamp (result i64)))
+              (import "env" "eth2_savePostState" (func $savePostState (param i32)))
+              (memory (export "memory") 1)
+              (func (export "main")
+                (i64.store (i32.const 0) (call $timestamp))
+                (call $savePostState (i32.const 0))))
+            "#,
+        );
+        let config = ExecutionConfig::default()
+            .with_genesis_time(1_000_000)
+            .with_slot(10);
+
+        let report = execute_code_with_config(
+            &code,
+            &Bytes32::default(),
+            &ShardBlockBody::default(),
+            &config,
+        )
+        .unwrap();
+        let expected = 1_000_000 + 10 * SECONDS_PER_SLOT;
+        assert_eq!(report.post_state.bytes[..8], expected.to_le_bytes());
+    }
+
+    #[test]
+    fn beacon_state_save_load_roundtrip() {
+        let beacon_state = BeaconState {
+            execution_scripts: vec![
+                ExecutionScript {
+                    code: loadstore_module(),
+                },
+                ExecutionScript {
+                    code: vec![1, 2, 3],
+                },
+            ],
+        };
+        let path = std::env::temp_dir().join(format!("scout-beacon-{}.bin", std::process::id()));
+
+        beacon_state.save(&path).unwrap();
+        let loaded = BeaconState::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.execution_scripts.len(), 2);
+        for (loaded, original) in loaded
+            .execution_scripts
+            .iter()
+            .zip(&beacon_state.execution_scripts)
+        {
+            assert_eq!(loaded.code, original.code);
+        }
+    }
+
+    #[test]
+    fn beacon_state_from_dir() {
+        let dir = std::env::temp_dir().join(format!("scout-scripts-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let identity = loadstore_module();
+        std::fs::write(dir.join("b.wasm"), &identity).unwrap();
+        std::fs::write(dir.join("a.wat"), "(module)").unwrap();
+        std::fs::write(dir.join("README"), "not a script").unwrap();
+
+        let beacon_state = BeaconState::from_dir(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let scripts = beacon_state.unwrap().execution_scripts;
+        assert_eq!(scripts.len(), 2);
+        assert_eq!(scripts[0].code, build_module("(module)"));
+        assert_eq!(scripts[1].code, identity);
+    }
+
+    #[test]
+    fn beacon_state_preflight() {
+        let mismatched = build_module(
+            r#"
+            (module
+              (import "env" "eth2_savePostState" (func (param i32 i32)))
+              (memory (export "memory") 1)
+              (func (export "main")))
+            "#,
+        );
+        let beacon_state = BeaconState {
+            execution_scripts: vec![
+                ExecutionScript {
+                    code: loadstore_module(),
+                },
+                ExecutionScript { code: mismatched },
+            ],
+        };
+
+        let results = beacon_state.preflight();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0], (0, Ok(())));
+        assert_eq!(results[1].0, 1);
+        assert!(matches!(results[1].1, Err(ExecError::Instantiation(_))));
+    }
+
+    #[test]
+    fn beacon_state_load_truncated() {
+        let mut buf = Vec::new();
+        BeaconState {
+            execution_scripts: vec![ExecutionScript { code: vec![1; 16] }],
+        }
+        .write_to(&mut buf)
+        .unwrap();
+        buf.truncate(buf.len() - 1);
+
+        let err = BeaconState::read_from(&mut &buf[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "eth2_blockDataSize reported 4 bytes for a body of 3")]
+    fn stale_block_data_size_is_detected() {
+        let body = ShardBlockBody {
+            data: vec![1, 2, 3, 4].into(),
+        };
+        let mut view = BlockDataView::new(&body);
+        let size = view.len() as i32;
+        debug_assert_block_data_size(size, view.data);
+
+        // A size cached before the body changed no longer matches it.
+        view.set_data(&[1, 2, 3]);
+        debug_assert_block_data_size(size, view.data);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn memory_access_invariants_hold_at_memory_end() {
+        // Every memory accessing host function touching the last bytes of the memory.
+        let code = build_module(
+            r#"
+            (module
+              (import "env" "eth2_loadPreState" (func $loadPreState (param i32)))
+              (import "env" "eth2_blockDataCopy" (func $blockDataCopy (param i32 i32 i32)))
+              (import "env" "eth2_savePostState" (func $savePostState (param i32)))
+              (import "env" "eth2_beginDeposit" (func $beginDeposit))
+              (import "env" "eth2_depositWrite" (func $depositWrite (param i32 i32)))
+              (import "env" "eth2_endDeposit" (func $endDeposit))
+              (memory (export "memory") 1)
+              (func (export "main")
+                (call $loadPreState (i32.const 65504))
+                (call $blockDataCopy (i32.const 65532) (i32.const 0) (i32.const 4))
+                (call $beginDeposit)
+                (call $depositWrite (i32.const 65352) (i32.const 184))
+                (call $endDeposit)
+                (call $savePostState (i32.const 65504))))
+            "#,
+        );
+        let block_data = ShardBlockBody {
+            data: vec![9; 4].into(),
+        };
+
+        let report = execute_code(&code, &Bytes32::from([7u8; 32]), &block_data).unwrap();
+        assert_eq!(report.post_state.bytes[..28], [7u8; 28]);
+        assert_eq!(report.post_state.bytes[28..], [9u8; 4]);
+        assert_eq!(report.deposits.len(), 1);
+
+        let code = block_copy_script(0, 0);
+        let result = execute_code(&code, &Bytes32::default(), &block_data);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn argument_wrappers_reinterpret_as_unsigned() {
+        let values = [
+            RuntimeValue::I32(0),
+            RuntimeValue::I32(i32::MAX),
+            RuntimeValue::I32(-1),
+            RuntimeValue::I32(i32::MIN),
+        ];
+        let args = RuntimeArgs::from(&values[..]);
+
+        assert_eq!(arg_ptr(&args, 0), 0);
+        assert_eq!(arg_ptr(&args, 1), 0x7fff_ffff);
+        assert_eq!(arg_ptr(&args, 2), u32::MAX);
+        assert_eq!(arg_ptr(&args, 3), 0x8000_0000);
+        assert_eq!(arg_len(&args, 0), 0);
+        assert_eq!(arg_len(&args, 2), u32::MAX);
+        assert_eq!(arg_len(&args, 3), 0x8000_0000);
+    }
+
+    #[test]
+    fn call_env_returns_callee_post_state() {
+        let scripts = [
+            ExecutionScript {
+                code: call_env_script(1),
+            },
+            ExecutionScript {
+                code: block_copy_script(0, 32),
+            },
+        ];
+        let context = CallContext {
+            scripts: &scripts,
+            depth: 0,
+            ..Default::default()
+        };
+
+        let report = execute_code_in_context(
+            &scripts[0].code,
+            &Bytes32::default(),
+            &ShardBlockBody::default(),
+            &ExecutionConfig::default(),
+            context,
+        )
+        .unwrap();
+        assert_eq!(
+            &report.post_state.bytes,
+            b"call input, padded to 32 bytes.."
+        );
+    }
+
+    #[test]
+    fn parent_root() {
+        let code = parent_root_module();
+        let parent_root = Bytes32::from([7u8; 32]);
+        let context = CallContext {
+            parent_root,
+            ..Default::default()
+        };
+
+        let report = execute_code_in_context(
+            &code,
+            &Bytes32::default(),
+            &ShardBlockBody::default(),
+            &ExecutionConfig::default(),
+            context,
+        )
+        .unwrap();
+        assert_eq!(report.post_state, parent_root);
+    }
+
+    #[test]
+    fn block_data_root() {
+        let script = |ptr: u32| {
+            build_module(&format!(
+                r#"
+                (module
+                  (import "env" "eth2_blockDataRoot" (func $blockDataRoot (param i32)))
+                  (import "env" "eth2_savePostState" (func $savePostState (param i32)))
+                  (memory (export "memory") 1)
+                  (func (export "main")
+                    (call $blockDataRoot (i32.const 32))
+                    (call $blockDataRoot (i32.const {}))
+                    (call $savePostState (i32.const 0))))
+                "#,
+                ptr
+            ))
+        };
+        let body = ShardBlockBody {
+            data: b"a block body".to_vec().into(),
+        };
+        let expected = Bytes32::from(<[u8; 32]>::from(Sha256::digest(b"a block body")));
+
+        let report = execute_code(&script(0), &Bytes32::default(), &body).unwrap();
+        assert_eq!(report.post_state, expected);
+        assert_eq!(
+            execute_code(&script(65536 - 16), &Bytes32::default(), &body).unwrap_err(),
+            ExecError::MemoryAccessOutOfBounds
+        );
+    }
+
+    #[test]
+    fn parent_block_links_headers() {
+        let code = parent_root_module();
+        let beacon_state = BeaconState {
+            execution_scripts: vec![ExecutionScript { code }],
+        };
+        let block = ShardBlock {
+            env: 0,
+            data: ShardBlockBody {
+                data: vec![1, 2, 3].into(),
+            },
+        };
+        let mut state = ShardState {
+            exec_env_states: vec![Bytes32::default()],
+            ..Default::default()
+        };
+        let process = |state: &mut ShardState, block: Option<ShardBlock>| {
+            process_shard_block(
+                state,
+                &beacon_state,
+                block,
+                &ExecutionConfig::default(),
+                &mut Metrics::default(),
+            )
+            .unwrap()
+        };
+
+        assert_eq!(state.parent_root(), Bytes32::default());
+        process(&mut state, Some(block.clone()));
+        let first = state.parent_block.clone().unwrap();
+        assert_eq!(first.slot, 0);
+        assert_eq!(first.parent_root, Bytes32::default());
+        assert_eq!(first.body_root, block.root());
+        assert_eq!(first.state_root, state.latest_state_roots[0]);
+
+        // Empty slots keep the parent, the next block links to it.
+        process(&mut state, None);
+        process(&mut state, Some(block));
+        let second = state.parent_block.clone().unwrap();
+        assert_eq!(second.slot, 2);
+        assert_eq!(second.parent_root, first.hash_tree_root());
+        assert_eq!(state.exec_env_states[0], first.hash_tree_root());
+        assert_ne!(second.hash_tree_root(), first.hash_tree_root());
+    }
+
+    #[test]
+    fn state_root_with_hasher() {
+        /// Hashes the input reversed, standing in for an alternative hash function.
+        struct Reversed;
+
+        impl Hasher for Reversed {
+            fn hash(&self, input: &[u8]) -> Bytes32 {
+                let reversed: Vec<u8> = input.iter().rev().copied().collect();
+                Sha256Hasher.hash(&reversed)
+            }
+        }
+
+        let state = ShardState {
+            exec_env_states: vec![Bytes32::from([1; 32])],
+            ..Default::default()
+        };
+        assert_eq!(state.state_root(), state.state_root_with(&Sha256Hasher));
+        assert_ne!(state.state_root(), state.state_root_with(&Reversed));
+    }
+
+    #[test]
+    fn call_env_shares_gas_limit() {
+        let gas_left = gas_left_module();
+        let scripts = [
+            ExecutionScript {
+                code: call_env_script(1),
+            },
+            ExecutionScript { code: gas_left },
+        ];
+        let context = CallContext {
+            scripts: &scripts,
+            ..Default::default()
+        };
+        let run = |config: &ExecutionConfig| {
+            execute_code_in_context(
+                &scripts[0].code,
+                &Bytes32::default(),
+                &ShardBlockBody::default(),
+                config,
+                context,
+            )
+        };
+
+        // Gas used by the callee counts against the limit of the caller.
+        let gas_used = run(&ExecutionConfig::default()).unwrap().gas_used;
+        let report = run(&ExecutionConfig::default().with_gas_limit(gas_used)).unwrap();
+        assert_eq!(report.gas_used, gas_used);
+        let err = run(&ExecutionConfig::default().with_gas_limit(gas_used - 1)).unwrap_err();
+        assert_eq!(err, ExecError::OutOfGas);
+
+        // Both scripts are charged up front, so the callee sees what neither of them uses.
+        let report = run(&ExecutionConfig::default().with_gas_limit(1000)).unwrap();
+        let mut gas_left = [0u8; 8];
+        gas_left.copy_from_slice(&report.post_state.bytes[..8]);
+        assert_eq!(i64::from_le_bytes(gas_left), 1000 - report.gas_used as i64);
+    }
+
+    #[test]
+    fn call_env_shares_host_calls() {
+        let emit_log = build_module(
+            r#"
+            (module
+              (import "env" "eth2_emitLog" (func $emitLog (param i32 i32 i32 i32)))
+              (memory (export "memory") 1)
+              (data (i32.const 0) "callee")
+              (func (export "main")
+                (call $emitLog (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 6))
+                (call $emitLog (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 6))))
+            "#,
+        );
+        let scripts = [
+            ExecutionScript {
+                code: call_env_script(1),
+            },
+            ExecutionScript { code: emit_log },
+        ];
+        let context = CallContext {
+            scripts: &scripts,
+            ..Default::default()
+        };
+        let run = |config: &ExecutionConfig| {
+            execute_code_in_context(
+                &scripts[0].code,
+                &Bytes32::default(),
+                &ShardBlockBody::default(),
+                config,
+                context,
+            )
+        };
+
+        // The call, both logs of the callee and saving the post-state.
+        let report = run(&ExecutionConfig::default().with_max_host_calls(4)).unwrap();
+        assert_eq!(report.host_calls["eth2_callEnv"], 1);
+        assert_eq!(report.host_calls["eth2_emitLog"], 2);
+        assert_eq!(report.host_calls["eth2_savePostState"], 1);
+        assert_eq!(report.logs.len(), 2);
+        assert_eq!(report.logs[0].data, b"callee");
+
+        let err = run(&ExecutionConfig::default().with_max_host_calls(3)).unwrap_err();
+        assert_eq!(err, ExecError::HostCallLimitExceeded);
+        let err = run(&ExecutionConfig::default().with_max_host_calls(2)).unwrap_err();
+        assert_eq!(err, ExecError::HostCallLimitExceeded);
+    }
+
+    #[test]
+    fn call_env_depth_is_bounded() {
+        let scripts = [ExecutionScript {
+            code: call_env_script(0),
+        }];
+        let context = CallContext {
+            scripts: &scripts,
+            depth: 0,
+            ..Default::default()
+        };
+
+        let result = execute_code_in_context(
+            &scripts[0].code,
+            &Bytes32::default(),
+            &ShardBlockBody::default(),
+            &ExecutionConfig::default(),
+            context,
+        );
+        assert_eq!(result.unwrap_err(), ExecError::EnvCallDepthExceeded);
+
+        let result = execute_code(
+            &scripts[0].code,
+            &Bytes32::default(),
+            &ShardBlockBody::default(),
+        );
+        assert_eq!(result.unwrap_err(), ExecError::UnknownEnvironment(0));
+    }
+
+    #[test]
+    fn call_env_depth_is_configurable() {
+        // Environments 0 and 1 call each other.
+        let scripts = [
+            ExecutionScript {
+                code: call_env_script(1),
+            },
+            ExecutionScript {
+                code: call_env_script(0),
+            },
+            ExecutionScript {
+                code: block_copy_script(0, 32),
+            },
+        ];
+        let context = CallContext {
+            scripts: &scripts,
+            ..Default::default()
+        };
+        let run = |env: usize, depth: usize| {
+            execute_code_in_context(
+                &scripts[env].code,
+                &Bytes32::default(),
+                &ShardBlockBody::default(),
+                &ExecutionConfig::default().with_max_call_env_depth(depth),
+                context,
+            )
+            .map(|_| ())
+        };
+
+        assert_eq!(run(0, 3), Err(ExecError::EnvCallDepthExceeded));
+        assert_eq!(run(0, 0), Err(ExecError::EnvCallDepthExceeded));
+        assert_eq!(run(1, 5), Err(ExecError::EnvCallDepthExceeded));
+
+        // A single call into environment 2 needs exactly one level.
+        let code = call_env_script(2);
+        let run = |depth: usize| {
+            execute_code_in_context(
+                &code,
+                &Bytes32::default(),
+                &ShardBlockBody::default(),
+                &ExecutionConfig::default().with_max_call_env_depth(depth),
+                context,
+            )
+            .map(|_| ())
+        };
+        assert_eq!(run(0), Err(ExecError::EnvCallDepthExceeded));
+        assert_eq!(run(1), Ok(()));
+    }
+
+    #[test]
+    fn aligned_copies_policy() {
+        let copy_to = |ptr: u32| {
+            build_module(&format!(
+                r#"
+                (module
+                  (import "env" "eth2_blockDataCopy" (func $blockDataCopy (param i32 i32 i32)))
+                  (memory (export "memory") 1)
+                  (func (export "main")
+                    (call $blockDataCopy (i32.const {}) (i32.const 0) (i32.const 4))))
+                "#,
+                ptr
+            ))
+        };
+        let block_data = ShardBlockBody {
+            data: vec![1; 4].into(),
+        };
+        let config = ExecutionConfig::default().with_aligned_copies(8);
+
+        let result =
+            execute_code_with_config(&copy_to(4), &Bytes32::default(), &block_data, &config);
+        assert_eq!(result.unwrap_err(), ExecError::UnalignedCopy(4));
+        assert!(
+            execute_code_with_config(&copy_to(16), &Bytes32::default(), &block_data, &config)
+                .is_ok()
+        );
+        assert!(execute_code(&copy_to(4), &Bytes32::default(), &block_data).is_ok());
+    }
+
+    #[test]
+    fn state_diff_reports_changed_env_only() {
+        let pre = ShardState {
+            exec_env_states: vec![Bytes32::from([1; 32]), Bytes32::from([2; 32])],
+            ..Default::default()
+        };
+        let mut post = pre.clone();
+        post.exec_env_states[1] = Bytes32::from([3; 32]);
+
+        let diff = StateDiff::between(&pre, &post);
+        assert_eq!(
+            diff.changed_envs,
+            vec![(1, Bytes32::from([2; 32]), Bytes32::from([3; 32]))]
+        );
+        assert_eq!(
+            diff.to_string(),
+            format!("env 1: 0x{} -> 0x{}\n", "02".repeat(32), "03".repeat(32))
+        );
+    }
+
+    #[test]
+    fn block_data_empty_flag() {
+        let code = build_module(
+            r#"
+            (module
+              (import "env" "eth2_blockDataEmpty" (func $blockDataEmpty (result i32)))
+              (import "env" "eth2_savePostState" (func $savePostState (param i32)))
+              (memory (export "memory") 1)
+              (func (export "main")
+                (i32.store (i32.const 0) (call $blockDataEmpty))
+                (call $savePostState (i32.const 0))))
+            "#,
+        );
+
+        let report = execute_code(&code, &Bytes32::default(), &ShardBlockBody::default()).unwrap();
+        assert_eq!(report.post_state.bytes[0], 1);
+        let block_data = ShardBlockBody {
+            data: vec![0].into(),
+        };
+        let report = execute_code(&code, &Bytes32::default(), &block_data).unwrap();
+        assert_eq!(report.post_state.bytes[0], 0);
+    }
+
+    #[test]
+    fn block_processing_error_context() {
+        let beacon_state = BeaconState {
+            execution_scripts: vec![
+                ExecutionScript::default(),
+                ExecutionScript { code: vec![0; 4] },
+            ],
+        };
+        let mut state = ShardState {
+            exec_env_states: vec![Bytes32::default()],
+            slot: 7,
+            ..Default::default()
+        };
+        let block = ShardBlock {
+            env: 1,
+            data: ShardBlockBody::default(),
+        };
+
+        let err = process_shard_block(
+            &mut state,
+            &beacon_state,
+            Some(block),
+            &ExecutionConfig::default(),
+            &mut Metrics::default(),
+        )
+        .unwrap_err();
+        assert_eq!(err.slot, 7);
+        assert_eq!(err.env, 1);
+        assert!(matches!(err.source, ExecError::InvalidModule(_)));
+    }
+
+    #[test]
+    fn memset_fills_low_byte() {
+        let code = memset_script(8, 0x1ff, 8);
+        let report = execute_code(&code, &Bytes32::default(), &ShardBlockBody::default()).unwrap();
+        assert_eq!(report.post_state.bytes[..8], [0xaa; 8]);
+        assert_eq!(report.post_state.bytes[8..16], [0xff; 8]);
+        assert_eq!(report.post_state.bytes[16..], [0xaa; 16]);
+
+        let code = memset_script(65530, 0, 16);
+        let result = execute_code(&code, &Bytes32::default(), &ShardBlockBody::default());
+        assert_eq!(result.unwrap_err(), ExecError::MemoryAccessOutOfBounds);
+    }
+
+    #[test]
+    fn memcpy_overlapping_regions() {
+        let run = |dst, src, length| {
+            execute_code(
+                &memcpy_script(dst, src, length),
+                &Bytes32::default(),
+                &ShardBlockBody::default(),
+            )
+            .map(|report| report.post_state.bytes[..16].to_vec())
+        };
+
+        assert_eq!(run(2, 0, 8).unwrap(), b"0101234567abcdef");
+        assert_eq!(run(0, 2, 8).unwrap(), b"2345678989abcdef");
+        assert_eq!(run(65530, 0, 8), Err(ExecError::MemoryAccessOutOfBounds));
+        assert_eq!(run(0, 65530, 8), Err(ExecError::MemoryAccessOutOfBounds));
+    }
+
+    #[test]
+    fn memcmp() {
+        let run = |a: u32, b: u32, length: u32| {
+            let code = build_module(&format!(
+                r#"
+                (module
+                  (import "env" "eth2_memcmp" (func $memcmp (param i32 i32 i32) (result i32)))
+                  (import "env" "eth2_savePostState" (func $savePostState (param i32)))
+                  (memory (export "memory") 1)
+                  (data (i32.const 0) "abcdabce")
+                  (func (export "main")
+                    (i32.store (i32.const 64)
+                      (call $memcmp (i32.const {}) (i32.const {}) (i32.const {})))
+                    (call $savePostState (i32.const 64))))
+                "#,
+                a, b, length
+            ));
+            execute_code(&code, &Bytes32::default(), &ShardBlockBody::default())
+                .map(|report| report.post_state.bytes[0])
+        };
+
+        assert_eq!(run(0, 4, 3), Ok(0));
+        assert_eq!(run(0, 4, 4), Ok(1));
+        assert_eq!(run(0, 4, 0), Ok(0));
+        assert_eq!(run(0, 65530, 8), Err(ExecError::MemoryAccessOutOfBounds));
+        assert_eq!(run(65530, 0, 8), Err(ExecError::MemoryAccessOutOfBounds));
+    }
+
+    #[test]
+    fn block_data_view_reflects_body() {
+        let body = ShardBlockBody {
+            data: vec![1, 2, 3, 4].into(),
+        };
+        let view = BlockDataView::new(&body);
+        assert_eq!(view.len(), 4);
+        assert!(!view.is_empty());
+        assert_eq!(view.get(1, 2), Some(&[2, 3][..]));
+        assert_eq!(view.get(4, 0), Some(&[][..]));
+        assert_eq!(view.get(3, 2), None);
+        assert_eq!(view.get(1, usize::MAX), None);
+
+        assert!(BlockDataView::new(&ShardBlockBody::default()).is_empty());
+    }
+
+    #[test]
+    fn deposit_sink_receives_deposits_immediately() {
+        // Pushes two deposits, then traps.
+        let code = build_module(
+            r#"
+            (module
+              (import "env" "eth2_pushNewDeposit" (func $pushNewDeposit (param i32)))
+              (memory (export "memory") 1)
+              (func (export "main")
+                (call $pushNewDeposit (i32.const 0))
+                (call $pushNewDeposit (i32.const 0))
+                (unreachable)))
+            "#,
+        );
+        let mut received = 0;
+        let result = execute_code_with_deposit_sink(
+            &code,
+            &Bytes32::default(),
+            &ShardBlockBody::default(),
+            &ExecutionConfig::default(),
+            &mut |_| received += 1,
+        );
+        assert!(result.is_err());
+        assert_eq!(received, 2);
+
+        let mut received = 0;
+        let report = execute_code_with_deposit_sink(
+            &push_deposits_script(0, 3),
+            &Bytes32::default(),
+            &ShardBlockBody::default(),
+            &ExecutionConfig::default(),
+            &mut |_| received += 1,
+        )
+        .unwrap();
+        assert_eq!(received, 3);
+        assert_eq!(report.deposits.len(), 3);
+    }
+
+    #[test]
+    fn reserved_deposits_do_not_reallocate() {
+        let code = push_deposits_script(3, 3);
+        let deposits = execute_code(&code, &Bytes32::default(), &ShardBlockBody::default())
+            .unwrap()
+            .deposits;
+        assert_eq!(deposits.len(), 3);
+        assert_eq!(deposits.capacity(), 3);
+
+        let config = ExecutionConfig::default().with_expected_deposits(5);
+        let code = push_deposits_script(0, 5);
+        let deposits = execute_code_with_config(
+            &code,
+            &Bytes32::default(),
+            &ShardBlockBody::default(),
+            &config,
+        )
+        .unwrap()
+        .deposits;
+        assert_eq!(deposits.capacity(), 5);
+    }
+
+    #[test]
+    fn reserved_deposits_capped_by_max_deposits() {
+        let config = ExecutionConfig::default().with_max_deposits(4);
+
+        let code = push_deposits_script(1_000_000, 4);
+        let deposits = execute_code_with_config(
+            &code,
+            &Bytes32::default(),
+            &ShardBlockBody::default(),
+            &config,
+        )
+        .unwrap()
+        .deposits;
+        assert_eq!(deposits.capacity(), 4);
+
+        let code = push_deposits_script(0, 5);
+        let result = execute_code_with_config(
+            &code,
+            &Bytes32::default(),
+            &ShardBlockBody::default(),
+            &config,
+        );
+        assert_eq!(result.unwrap_err(), ExecError::DepositLimitExceeded);
+    }
+
+    #[test]
+    fn block_verifier_rejects_before_execution() {
+        #[derive(Debug)]
+        struct RejectAllVerifier;
+
+        impl BlockSignatureVerifier for RejectAllVerifier {
+            fn verify(&self, _block: &ShardBlock) -> Result<(), ExecError> {
+                Err(ExecError::InvalidSignature)
+            }
+        }
+
+        let beacon_state = BeaconState {
+            execution_scripts: vec![ExecutionScript {
+                code: push_deposits_script(0, 1),
+            }],
+        };
+        let mut state = ShardState {
+            exec_env_states: vec![Bytes32::default()],
+            ..Default::default()
+        };
+        let mut metrics = Metrics::default();
+        let err = process_shard_block_with_verifier(
+            &mut state,
+            &beacon_state,
+            Some(ShardBlock::default()),
+            &ExecutionConfig::default(),
+            &mut metrics,
+            &RejectAllVerifier,
+        )
+        .unwrap_err();
+        assert_eq!(err.source, ExecError::InvalidSignature);
+        assert_eq!(state.slot, 0);
+        assert!(metrics
+            .metrics_text()
+            .contains("scout_blocks_processed_total 0\n"));
+
+        let failures = process_shard_blocks_with_verifier(
+            &mut state,
+            &beacon_state,
+            vec![Ok(ShardBlock::default()); 2],
+            &ExecutionConfig::default(),
+            &mut metrics,
+            BatchPolicy::SkipAndContinue,
+            &RejectAllVerifier,
+        )
+        .unwrap();
+        assert_eq!(
+            failures,
+            vec![
+                (0, ExecError::InvalidSignature),
+                (1, ExecError::InvalidSignature)
+            ]
+        );
+        assert_eq!(state.slot, 0);
+        assert!(metrics
+            .metrics_text()
+            .contains("scout_blocks_processed_total 0\n"));
+    }
+
+    #[test]
+    fn too_many_deposits_rejects_block() {
+        let process = |count: u32| {
+            let beacon_state = BeaconState {
+                execution_scripts: vec![ExecutionScript {
+                    code: push_deposits_script(0, count),
+                }],
+            };
+            let mut state = ShardState {
+                exec_env_states: vec![Bytes32::default()],
+                ..Default::default()
+            };
+            process_shard_block(
+                &mut state,
+                &beacon_state,
+                Some(ShardBlock::default()),
+                &ExecutionConfig::default(),
+                &mut Metrics::default(),
+            )
+            .map(|_| state.slot)
+        };
+
+        assert_eq!(process(MAX_DEPOSITS_PER_BLOCK as u32).unwrap(), 1);
+        let err = process(MAX_DEPOSITS_PER_BLOCK as u32 + 1).unwrap_err();
+        assert_eq!(
+            err.source,
+            ExecError::TooManyDeposits(MAX_DEPOSITS_PER_BLOCK + 1)
+        );
+    }
+
+    /// Renders the deterministic parts of a report, leaving out the execution time.
+    fn golden_report(report: &ExecutionReport) -> String {
+        let mut out = format!(
+            "post_state: {}\ndeposits: {}\ngas_used: {}\nhost_calls:\n",
+            report.post_state,
+            report.deposits.len(),
+            report.gas_used
+        );
+        for (name, count) in &report.host_calls {
+            out.push_str(&format!("  {}: {}\n", name, count));
+        }
+        out
+    }
+
+    /// Compares the helloworld execution against `tests/fixtures/helloworld.golden`.
+    /// Run with `UPDATE_GOLDEN=1` to regenerate the expected file.
+    #[test]
+    fn helloworld_golden() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        let code = wat::parse_file(fixtures.join("helloworld.wat")).unwrap();
+        let report = execute_code(
+            &code,
+            &Bytes32::from([0x42; 32]),
+            &ShardBlockBody::default(),
+        )
+        .unwrap();
+        let actual = golden_report(&report);
+
+        let golden = fixtures.join("helloworld.golden");
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::write(&golden, &actual).unwrap();
+        }
+        let expected = std::fs::read_to_string(&golden).unwrap();
+        assert_eq!(
+            actual, expected,
+            "rerun with UPDATE_GOLDEN=1 if the change is intended"
+        );
+    }
+
+    /// The conformance vectors files in `dir`, in name order.
+    fn vectors_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "vectors") {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        Ok(paths)
+    }
+
+    /// Runs a conformance vectors file. It names its script with a `script: <path>` line, relative
+    /// to the file, followed by `<pre_state> <block_data> <post_state>` lines in hex. Returns the
+    /// number of vectors passed, or `None` if the script is absent, like the compiled examples of
+    /// ewasm/scout.
+    fn run_vectors_file(path: &Path) -> Result<Option<usize>, String> {
+        let contents = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let mut lines = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+        let script = lines
+            .next()
+            .and_then(|line| line.strip_prefix("script:"))
+            .map(|script| path.with_file_name(script.trim()))
+            .ok_or("missing script line")?;
+        if !script.exists() {
+            return Ok(None);
+        }
+        let code = ExecutionScript::from_file(&script)
+            .map_err(|e| format!("{}: {}", script.display(), e))?
+            .code;
+
+        let mut passed = 0;
+        for (number, line) in lines.enumerate() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (pre_state, data, post_state) = match fields[..] {
+                [pre_state, data, post_state] => (pre_state, data, post_state),
+                _ => return Err(format!("vector {}: malformed", number)),
+            };
+            let vector_error = |e: String| format!("vector {}: {}", number, e);
+            let pre_state = parse_bytes32(pre_state).map_err(vector_error)?;
+            let data = parse_block_data(data).map_err(vector_error)?;
+            let expected = parse_bytes32(post_state).map_err(vector_error)?;
+            let report = execute_code(&code, &pre_state, &ShardBlockBody { data })
+                .map_err(|e| vector_error(e.to_string()))?;
+            if report.post_state != expected {
+                return Err(vector_error(format!(
+                    "expected post-state {}, got {}",
+                    expected, report.post_state
+                )));
+            }
+            passed += 1;
+        }
+        Ok(Some(passed))
+    }
+
+    /// Runs the vectors in `tests/conformance`, skipping those whose script is absent.
+    #[test]
+    fn conformance_vectors() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/conformance");
+        for path in vectors_files(&dir).unwrap() {
+            match run_vectors_file(&path) {
+                Ok(Some(_)) => {}
+                Ok(None) => eprintln!("warning: skipping {}, script is absent", path.display()),
+                Err(e) => panic!("{}: {}", path.display(), e),
+            }
+        }
+    }
+
+    /// Runs the modules in `tests/corpus`, each a minimized input for a panic or an edge case
+    /// described at its top, and checks they fail with the expected error.
+    #[test]
+    fn corpus_modules_fail_cleanly() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+        let corpus = [
+            // Zero bytes of code, which can't carry a comment of its own.
+            ("empty.wasm", ExecError::EmptyScript),
+            (
+                "empty_module.wat",
+                ExecError::MissingExport("memory".to_string()),
+            ),
+            (
+                "missing_memory.wat",
+                ExecError::MissingExport("memory".to_string()),
+            ),
+            (
+                "huge_memory.wat",
+                ExecError::InvalidModule(
+                    "Validation: initial memory size must be at most 65536 pages".to_string(),
+                ),
+            ),
+            ("start_function.wat", ExecError::UnexpectedStart),
+            ("reversed_copy.wat", ExecError::ReversedBlockDataRange(1, 0)),
+            ("deep_recursion.wat", ExecError::CallDepthExceeded),
+        ];
+
+        for (name, expected) in corpus {
+            let script = ExecutionScript::from_file(dir.join(name)).unwrap();
+            let result = execute_code(
+                &script.code,
+                &Bytes32::default(),
+                &ShardBlockBody::default(),
+            );
+            assert_eq!(result.map(|_| ()), Err(expected), "{}", name);
+        }
+    }
+
+    #[test]
+    fn state_transfer_follows_state_length() {
+        struct State16([u8; 16]);
+
+        impl AsRef<[u8]> for State16 {
+            fn as_ref(&self) -> &[u8] {
+                &self.0
+            }
+        }
+
+        impl AsMut<[u8]> for State16 {
+            fn as_mut(&mut self) -> &mut [u8] {
+                &mut self.0
+            }
+        }
+
+        let pre_state = Bytes32::from([0xff; 32]);
+        let body = ShardBlockBody::default();
+        let config = ExecutionConfig::default();
+        let runtime =
+            Runtime::new(&[], &pre_state, &body, &config, CallContext::default()).unwrap();
+
+        runtime.load_state(0, &State16([7; 16])).unwrap();
+        assert_eq!(
+            runtime.read_memory(0, 17).unwrap(),
+            [&[7; 16][..], &[0]].concat()
+        );
+
+        let mut state = State16([0; 16]);
+        runtime.write_memory(16, &[9; 16]).unwrap();
+        runtime.save_state(8, &mut state).unwrap();
+        assert_eq!(state.0, [&[7; 8][..], &[9; 8]].concat()[..]);
+
+        // A zero-length state touches no memory at all, even out of bounds.
+        runtime
+            .load_state(WASM_PAGE_SIZE as u32, &[0u8; 0])
+            .unwrap();
+        runtime
+            .save_state(WASM_PAGE_SIZE as u32, &mut [0u8; 0])
+            .unwrap();
+    }
+
+    #[test]
+    fn memory_size_uses_page_size() {
+        assert_eq!(WASM_PAGE_SIZE, 65536);
+        assert_eq!(WASM_PAGE_SIZE, wasmi::LINEAR_MEMORY_PAGE_SIZE.0);
+
+        let memory = MemoryInstance::alloc(Pages(2), None).unwrap();
+        assert_eq!(memory_size_bytes(&memory), 2 * WASM_PAGE_SIZE);
+    }
+
+    #[test]
+    fn alloc_memory_reports_failure() {
+        let memory = alloc_memory(Pages(1), Some(Pages(1))).unwrap();
+        assert_eq!(memory_size_bytes(&memory), WASM_PAGE_SIZE);
+
+        // More pages than a 32-bit address space holds can never be allocated.
+        assert!(matches!(
+            alloc_memory(Pages(65537), None),
+            Err(ExecError::MemoryAllocationFailed)
+        ));
+    }
+
+    #[test]
+    fn trace_records_host_calls() {
+        let code = loadstore_module();
+        let config = ExecutionConfig::default().with_trace(true);
+        let run = || {
+            execute_code_with_config(
+                &code,
+                &Bytes32::from([5u8; 32]),
+                &ShardBlockBody::default(),
+                &config,
+            )
+            .unwrap()
+            .trace
+        };
+        let trace = run();
+        assert_eq!(trace, run());
+
+        let names: Vec<_> = trace.iter().map(|call| call.name).collect();
+        assert_eq!(names, ["eth2_loadPreState", "eth2_savePostState"]);
+        assert_eq!(trace[0].args, [RuntimeValue::I32(0)]);
+        // Loading the pre-state changes memory, saving it doesn't.
+        assert_ne!(trace[0].mem_before_hash, trace[0].mem_after_hash);
+        assert_eq!(trace[1].mem_before_hash, trace[1].mem_after_hash);
+
+        let untraced = execute_code(&code, &Bytes32::default(), &ShardBlockBody::default());
+        assert!(untraced.unwrap().trace.is_empty());
+    }
+
+    #[test]
+    fn historical_state_root_lookup() {
+        let lookup = |slots_ago: u32| {
+            build_module(&format!(
+                r#"
+                (module
+                  (import "env" "eth2_historicalStateRoot" (func $historicalStateRoot (param i32 i32) (result i32)))
+                  (import "env" "eth2_savePostState" (func $savePostState (param i32)))
+                  (memory (export "memory") 1)
+                  (func (export "main")
+                    (i32.store (i32.const 32) (call $historicalStateRoot (i32.const {}) (i32.const 0)))
+                    (call $savePostState (i32.const 0))
+                    (if (i32.eqz (i32.load (i32.const 32)))
+                      (then (call $savePostState (i32.const 32))))))
+                "#,
+                slots_ago
+            ))
+        };
+        let beacon_state = BeaconState {
+            execution_scripts: vec![
+                ExecutionScript { code: lookup(2) },
+                ExecutionScript { code: lookup(10) },
+            ],
+        };
+        let mut state = ShardState {
+            exec_env_states: vec![Bytes32::default()],
+            ..Default::default()
+        };
+        let process = |state: &mut ShardState, block| {
+            process_shard_block(
+                state,
+                &beacon_state,
+                block,
+                &ExecutionConfig::default(),
+                &mut Metrics::default(),
+            )
+            .unwrap()
+        };
+
+        let mut roots = Vec::new();
+        for _ in 0..3 {
+            roots.push(state.state_root());
+            process(&mut state, None);
+        }
+        assert_eq!(state.slot, 3);
+        assert_eq!(state.latest_state_roots, roots);
+
+        // Slot 3 looking two slots back sees the root recorded at the end of slot 1.
+        process(&mut state, Some(ShardBlock::default()));
+        assert_eq!(state.exec_env_states[0], roots[1]);
+
+        // Ten slots back is outside of the four recorded slots, the script saves the zero flag.
+        let block = ShardBlock {
+            env: 1,
+            data: ShardBlockBody::default(),
+        };
+        process(&mut state, Some(block));
+        assert_eq!(state.exec_env_states[1], Bytes32::default());
+    }
+
+    #[test]
+    fn execute_across_envs_invalid_code() {
+        let results =
+            execute_across_envs(&[0u8; 4], &[Bytes32::default()], &ShardBlockBody::default());
+        match &results[0] {
+            Err(ExecError::InvalidModule(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn replay_is_deterministic() {
+        // Mixes the pre-state, the block, the seeded random stream and the timestamp into the
+        // post-state, pushing a deposit on the way.
+        let code = build_module(
+            r#"
+            (module
+              (import "env" "eth2_loadPreState" (func $loadPreState (param i32)))
+              (import "env" "eth2_blockDataCopy" (func $blockDataCopy (param i32 i32 i32)))
+              (import "env" "eth2_seededRandom" (func $seededRandom (param i32 i32)))
+              (import "env" "eth2_memcpy" (func $memcpy (param i32 i32 i32)))
+              (import "env" "eth2_timestamp" (func $timestamp (result i64)))
+              (import "env" "eth2_pushNewDeposit" (func $pushNewDeposit (param i32)))
+              (import "env" "eth2_savePostState" (func $savePostState (param i32)))
+              (memory (export "memory") 1)
+              (func $mix (param $offset i32)
+                (i64.store (local.get $offset)
+                  (i64.xor
+                    (i64.xor (i64.load (local.get $offset)) (i64.load offset=32 (local.get $offset)))
+                    (i64.load offset=64 (local.get $offset)))))
+              (func (export "main")
+                (call $loadPreState (i32.const 96))
+                (call $memcpy (i32.const 0) (i32.const 96) (i32.const 32))
+                (call $blockDataCopy (i32.const 32) (i32.const 0) (i32.const 32))
+                (call $seededRandom (i32.const 64) (i32.const 32))
+                (call $mix (i32.const 0))
+                (call $mix (i32.const 8))
+                (call $mix (i32.const 16))
+                (call $mix (i32.const 24))
+                (i64.store (i32.const 0) (i64.xor (i64.load (i32.const 0)) (call $timestamp)))
+                (call $pushNewDeposit (i32.const 256))
+                (call $savePostState (i32.const 0))))
+            "#,
+        );
+        let beacon_state = BeaconState {
+            execution_scripts: vec![ExecutionScript { code }],
+        };
+        let replay = || {
+            let mut state = ShardState {
+                exec_env_states: vec![Bytes32::default()],
+                ..Default::default()
+            };
+            let mut roots = Vec::new();
+            for i in 0..5u8 {
+                let block = ShardBlock {
+                    env: 0,
+                    data: ShardBlockBody {
+                        data: vec![i; 32].into(),
+                    },
+                };
+                process_shard_block(
+                    &mut state,
+                    &beacon_state,
+                    Some(block),
+                    &ExecutionConfig::default(),
+                    &mut Metrics::default(),
+                )
+                .unwrap();
+                roots.push(state.state_root());
+            }
+            (state, roots)
+        };
+
+        let (first, first_roots) = replay();
+        let (second, second_roots) = replay();
+        assert_ne!(first.exec_env_states[0], Bytes32::default());
+        assert_eq!(first.exec_env_states, second.exec_env_states);
+        assert_eq!(first.latest_state_roots, second.latest_state_roots);
+        assert_eq!(first_roots, second_roots);
+    }
 }
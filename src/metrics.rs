@@ -0,0 +1,93 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::ExecutionReport;
+
+/// Aggregates execution statistics across processed blocks.
+#[derive(Default, Clone, Debug)]
+pub struct Metrics {
+    blocks_processed: u64,
+    gas_used: u64,
+    host_calls: BTreeMap<&'static str, u64>,
+    execution_time: Duration,
+}
+
+impl Metrics {
+    pub fn record_execution(&mut self, report: &ExecutionReport) {
+        self.blocks_processed += 1;
+        self.gas_used += report.gas_used;
+        for (name, count) in &report.host_calls {
+            *self.host_calls.entry(name).or_insert(0) += count;
+        }
+        self.execution_time += report.execution_time;
+    }
+
+    pub fn gas_used(&self) -> u64 {
+        self.gas_used
+    }
+
+    pub fn average_execution_time(&self) -> Duration {
+        if self.blocks_processed == 0 {
+            return Duration::default();
+        }
+        // Divided as floats, as the block count may not fit the u32 a `Duration` divides by.
+        Duration::from_secs_f64(self.execution_time.as_secs_f64() / self.blocks_processed as f64)
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format.
+    pub fn metrics_text(&self) -> String {
+        let mut out = String::new();
+
+        writeln!(
+            out,
+            "# HELP scout_blocks_processed_total Shard blocks executed."
+        )
+        .unwrap();
+        writeln!(out, "# TYPE scout_blocks_processed_total counter").unwrap();
+        writeln!(
+            out,
+            "scout_blocks_processed_total {}",
+            self.blocks_processed
+        )
+        .unwrap();
+
+        writeln!(
+            out,
+            "# HELP scout_gas_used_total Gas used by all executions."
+        )
+        .unwrap();
+        writeln!(out, "# TYPE scout_gas_used_total counter").unwrap();
+        writeln!(out, "scout_gas_used_total {}", self.gas_used).unwrap();
+
+        writeln!(
+            out,
+            "# HELP scout_host_calls_total Host function calls by function."
+        )
+        .unwrap();
+        writeln!(out, "# TYPE scout_host_calls_total counter").unwrap();
+        for (name, count) in &self.host_calls {
+            writeln!(
+                out,
+                "scout_host_calls_total{{function=\"{}\"}} {}",
+                name, count
+            )
+            .unwrap();
+        }
+
+        writeln!(
+            out,
+            "# HELP scout_execution_time_seconds_average Average execution time per block."
+        )
+        .unwrap();
+        writeln!(out, "# TYPE scout_execution_time_seconds_average gauge").unwrap();
+        writeln!(
+            out,
+            "scout_execution_time_seconds_average {}",
+            self.average_execution_time().as_secs_f64()
+        )
+        .unwrap();
+
+        out
+    }
+}
@@ -0,0 +1,167 @@
+//! WebAssembly modules for tests, built from their text format. The unit tests use them
+//! directly, the integration tests through `tests/common`.
+
+/// Assembles a module from its text format.
+pub fn build_module(wat: &str) -> Vec<u8> {
+    wat::parse_str(wat).unwrap()
+}
+
+/// Loads the pre-state into memory and saves it back unchanged.
+pub fn loadstore_module() -> Vec<u8> {
+    build_module(
+        r#"
+        (module
+          (import "env" "eth2_loadPreState" (func $loadPreState (param i32)))
+          (import "env" "eth2_savePostState" (func $savePostState (param i32)))
+          (memory (export "memory") 1)
+          (func (export "main")
+            (call $loadPreState (i32.const 0))
+            (call $savePostState (i32.const 0))))
+        "#,
+    )
+}
+
+/// Exports a memory and a `main` which does nothing, so no post-state is saved.
+pub fn empty_main_module() -> Vec<u8> {
+    build_module(
+        r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "main")))
+        "#,
+    )
+}
+
+/// Traps as soon as `main` is entered.
+pub fn trap_module() -> Vec<u8> {
+    build_module(
+        r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "main") unreachable))
+        "#,
+    )
+}
+
+/// Saves the root of the parent block as the post-state.
+pub fn parent_root_module() -> Vec<u8> {
+    build_module(
+        r#"
+        (module
+          (import "env" "eth2_parentRoot" (func $parentRoot (param i32)))
+          (import "env" "eth2_savePostState" (func $savePostState (param i32)))
+          (memory (export "memory") 1)
+          (func (export "main")
+            (call $parentRoot (i32.const 0))
+            (call $savePostState (i32.const 0))))
+        "#,
+    )
+}
+
+/// Saves the gas left, as a little-endian i64 at the start of the post-state.
+pub fn gas_left_module() -> Vec<u8> {
+    build_module(
+        r#"
+        (module
+          (import "env" "eth2_gasLeft" (func $gasLeft (result i64)))
+          (import "env" "eth2_savePostState" (func $savePostState (param i32)))
+          (memory (export "memory") 1)
+          (func (export "main")
+            (i64.store (i32.const 0) (call $gasLeft))
+            (call $savePostState (i32.const 0))))
+        "#,
+    )
+}
+
+/// Copies `length` bytes of block data from `offset` and saves them as the post-state.
+pub fn block_copy_script(offset: u32, length: u32) -> Vec<u8> {
+    build_module(&format!(
+        r#"
+        (module
+          (import "env" "eth2_blockDataCopy" (func $blockDataCopy (param i32 i32 i32)))
+          (import "env" "eth2_savePostState" (func $savePostState (param i32)))
+          (memory (export "memory") 1)
+          (func (export "main")
+            (call $blockDataCopy (i32.const 0) (i32.const {}) (i32.const {}))
+            (call $savePostState (i32.const 0))))
+        "#,
+        offset, length
+    ))
+}
+
+/// Calls environment `env` with the 32 bytes at 0 as input, saving its output as post-state.
+pub fn call_env_script(env: u32) -> Vec<u8> {
+    build_module(&format!(
+        r#"
+        (module
+          (import "env" "eth2_callEnv" (func $callEnv (param i32 i32 i32 i32 i32) (result i32)))
+          (import "env" "eth2_savePostState" (func $savePostState (param i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 0) "call input, padded to 32 bytes..")
+          (func (export "main")
+            (drop (call $callEnv (i32.const {}) (i32.const 0) (i32.const 32) (i32.const 64) (i32.const 32)))
+            (call $savePostState (i32.const 64))))
+        "#,
+        env
+    ))
+}
+
+/// Holds "0123456789abcdef" at 0, runs the memcpy and saves the first 32 bytes.
+pub fn memcpy_script(dst: u32, src: u32, length: u32) -> Vec<u8> {
+    build_module(&format!(
+        r#"
+        (module
+          (import "env" "eth2_memcpy" (func $memcpy (param i32 i32 i32)))
+          (import "env" "eth2_savePostState" (func $savePostState (param i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 0) "0123456789abcdef")
+          (func (export "main")
+            (call $memcpy (i32.const {}) (i32.const {}) (i32.const {}))
+            (call $savePostState (i32.const 0))))
+        "#,
+        dst, src, length
+    ))
+}
+
+/// Fills memory at 0 with a 0xaa pattern, memsets the given range and saves the first
+/// 32 bytes as post-state.
+pub fn memset_script(ptr: u32, value: i32, length: u32) -> Vec<u8> {
+    build_module(&format!(
+        r#"
+        (module
+          (import "env" "eth2_memset" (func $memset (param i32 i32 i32)))
+          (import "env" "eth2_savePostState" (func $savePostState (param i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 0) "{}")
+          (func (export "main")
+            (call $memset (i32.const {}) (i32.const {}) (i32.const {}))
+            (call $savePostState (i32.const 0))))
+        "#,
+        "\\aa".repeat(32),
+        ptr,
+        value,
+        length
+    ))
+}
+
+/// Reserves room for `reserve` deposits, then pushes the deposit at 0 `count` times.
+pub fn push_deposits_script(reserve: u32, count: u32) -> Vec<u8> {
+    build_module(&format!(
+        r#"
+        (module
+          (import "env" "eth2_reserveDeposits" (func $reserveDeposits (param i32)))
+          (import "env" "eth2_pushNewDeposit" (func $pushNewDeposit (param i32)))
+          (memory (export "memory") 1)
+          (func (export "main")
+            (local $i i32)
+            (call $reserveDeposits (i32.const {}))
+            (block $done
+              (loop $push
+                (br_if $done (i32.ge_u (local.get $i) (i32.const {})))
+                (call $pushNewDeposit (i32.const 0))
+                (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                (br $push)))))
+        "#,
+        reserve, count
+    ))
+}
@@ -1,9 +1,184 @@
+use rustc_hex::{FromHex, ToHex};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::iter::FromIterator;
+use std::ops::Deref;
+use std::str::FromStr;
+
+use crate::ScoutError;
+
 /// An array of 256 bits.
-#[derive(Default, Copy, Clone, Debug)]
+#[derive(Default, Copy, Clone, Debug, PartialEq, Eq)]
 pub struct Bytes32 {
     pub bytes: [u8; 32],
 }
 
+impl Bytes32 {
+    /// Byte-wise XOR of the two values.
+    pub fn xor(&self, other: &Bytes32) -> Bytes32 {
+        let mut bytes = self.bytes;
+        for (byte, other) in bytes.iter_mut().zip(other.bytes.iter()) {
+            *byte ^= other;
+        }
+        Bytes32 { bytes }
+    }
+
+    /// SHA-256 of `self || other`, as used to combine two merkle nodes.
+    pub fn hash_with(&self, other: &Bytes32) -> Bytes32 {
+        self.hash_with_hasher(other, &Sha256Hasher)
+    }
+
+    /// Like `hash_with`, with the given hash function.
+    pub fn hash_with_hasher<H: Hasher>(&self, other: &Bytes32, hasher: &H) -> Bytes32 {
+        let mut input = [0u8; 64];
+        input[..32].copy_from_slice(&self.bytes);
+        input[32..].copy_from_slice(&other.bytes);
+        hasher.hash(&input)
+    }
+}
+
+/// The hash function used for merkleization and state roots.
+pub trait Hasher {
+    fn hash(&self, input: &[u8]) -> Bytes32;
+
+    /// Root of the binary merkle tree over the leaves, padded with zero leaves to a power of
+    /// two. The root of no leaves is the zero hash.
+    fn hash_tree_root(&self, leaves: &[Bytes32]) -> Bytes32
+    where
+        Self: Sized,
+    {
+        let mut nodes = leaves.to_vec();
+        nodes.resize(leaves.len().next_power_of_two(), Bytes32::default());
+        while nodes.len() > 1 {
+            nodes = nodes
+                .chunks(2)
+                .map(|pair| pair[0].hash_with_hasher(&pair[1], self))
+                .collect();
+        }
+        nodes.pop().unwrap_or_default()
+    }
+}
+
+#[derive(Default, Clone, Copy, Debug)]
+pub struct Sha256Hasher;
+
+impl Hasher for Sha256Hasher {
+    fn hash(&self, input: &[u8]) -> Bytes32 {
+        Bytes32 {
+            bytes: Sha256::digest(input).into(),
+        }
+    }
+}
+
+impl fmt::Display for Bytes32 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{}", self.bytes.to_hex())
+    }
+}
+
+impl AsRef<[u8]> for Bytes32 {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl AsMut<[u8]> for Bytes32 {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes
+    }
+}
+
+/// A variable-length byte string, such as a block body.
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+pub struct Bytes(pub Vec<u8>);
+
+impl Bytes {
+    /// Encodes the bytes as an SSZ list: a little-endian u32 length prefix followed by the data.
+    pub fn to_ssz(&self) -> Vec<u8> {
+        let mut encoded = Vec::with_capacity(4 + self.0.len());
+        encoded.extend_from_slice(&(self.0.len() as u32).to_le_bytes());
+        encoded.extend_from_slice(&self.0);
+        encoded
+    }
+
+    /// Decodes bytes encoded by `to_ssz`. The length prefix must match the rest of the buffer
+    /// exactly.
+    pub fn from_ssz(encoded: &[u8]) -> Result<Bytes, ScoutError> {
+        if encoded.len() < 4 {
+            return Err(ScoutError::MalformedSsz(format!(
+                "expected a 4 byte length prefix, got {} bytes",
+                encoded.len()
+            )));
+        }
+        let (prefix, data) = encoded.split_at(4);
+        let length = u32::from_le_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]) as usize;
+        if length != data.len() {
+            return Err(ScoutError::MalformedSsz(format!(
+                "length prefix declares {} bytes, {} present",
+                length,
+                data.len()
+            )));
+        }
+        Ok(Bytes(data.to_vec()))
+    }
+
+    /// Fails unless there are exactly `expected` bytes.
+    pub fn check_len(&self, expected: usize) -> Result<(), String> {
+        if self.0.len() != expected {
+            return Err(format!("expected {} bytes, got {}", expected, self.0.len()));
+        }
+        Ok(())
+    }
+}
+
+impl Deref for Bytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl AsRef<[u8]> for Bytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<Vec<u8>> for Bytes {
+    fn from(bytes: Vec<u8>) -> Self {
+        Bytes(bytes)
+    }
+}
+
+impl From<&[u8]> for Bytes {
+    fn from(bytes: &[u8]) -> Self {
+        Bytes(bytes.to_vec())
+    }
+}
+
+impl FromIterator<u8> for Bytes {
+    fn from_iter<I: IntoIterator<Item = u8>>(iter: I) -> Self {
+        Bytes(iter.into_iter().collect())
+    }
+}
+
+impl fmt::Display for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{}", self.0.to_hex())
+    }
+}
+
+/// Parses hex, with or without a `0x` prefix.
+impl FromStr for Bytes {
+    type Err = String;
+
+    fn from_str(hex: &str) -> Result<Self, Self::Err> {
+        let hex = hex.strip_prefix("0x").unwrap_or(hex);
+        hex.from_hex().map(Bytes).map_err(|e| e.to_string())
+    }
+}
+
 macro_rules! from_primitive_impl {
     ($f:ident, $size:expr, $to:ident) => {
         impl From<[$f; $size]> for $to {
@@ -41,7 +216,23 @@ from_type_for_primitive_impl!(Bytes32, u8, 32);
 
 #[cfg(test)]
 mod tests {
-    use super::Bytes32;
+    use super::{Bytes, Bytes32, Hasher, Sha256Hasher};
+    use sha2::{Digest, Sha256};
+
+    /// SHA-256 with a domain prefix, standing in for an alternative hash function.
+    struct PrefixedSha256;
+
+    impl Hasher for PrefixedSha256 {
+        fn hash(&self, input: &[u8]) -> Bytes32 {
+            let digest = Sha256::new()
+                .chain_update([1])
+                .chain_update(input)
+                .finalize();
+            Bytes32 {
+                bytes: digest.into(),
+            }
+        }
+    }
 
     macro_rules! test_conversions {
         ($type: ident, $size: expr, $test_name: ident) => {
@@ -66,4 +257,98 @@ mod tests {
     }
 
     test_conversions!(Bytes32, 32, test_bytes32);
+
+    #[test]
+    fn test_bytes32_display() {
+        let mut bytes = [0u8; 32];
+        bytes[0] = 0xab;
+        bytes[31] = 0x01;
+        assert_eq!(
+            Bytes32::from(bytes).to_string(),
+            "0xab00000000000000000000000000000000000000000000000000000000000001"
+        );
+    }
+
+    #[test]
+    fn test_bytes32_xor() {
+        let value = Bytes32::from([0x5a; 32]);
+        assert_eq!(value.xor(&Bytes32::default()), value);
+        assert_eq!(value.xor(&value), Bytes32::default());
+        assert_eq!(
+            value.xor(&Bytes32::from([0xff; 32])),
+            Bytes32::from([0xa5; 32])
+        );
+    }
+
+    #[test]
+    fn test_bytes32_hash_with() {
+        // The root of two zero leaves.
+        assert_eq!(
+            Bytes32::default()
+                .hash_with(&Bytes32::default())
+                .to_string(),
+            "0xf5a5fd42d16a20302798ef6ed309979b43003d2320d9f0e8ea9831a92759fb4b"
+        );
+        let (left, right) = (Bytes32::from([1; 32]), Bytes32::from([2; 32]));
+        assert_ne!(left.hash_with(&right), right.hash_with(&left));
+    }
+
+    #[test]
+    fn test_hash_tree_root() {
+        let leaves = [
+            Bytes32::from([1; 32]),
+            Bytes32::from([2; 32]),
+            Bytes32::from([3; 32]),
+        ];
+        let expected = leaves[0]
+            .hash_with(&leaves[1])
+            .hash_with(&leaves[2].hash_with(&Bytes32::default()));
+        assert_eq!(Sha256Hasher.hash_tree_root(&leaves), expected);
+        assert_eq!(Sha256Hasher.hash_tree_root(&leaves[..1]), leaves[0]);
+        assert_eq!(Sha256Hasher.hash_tree_root(&[]), Bytes32::default());
+
+        assert_ne!(
+            PrefixedSha256.hash_tree_root(&leaves),
+            Sha256Hasher.hash_tree_root(&leaves)
+        );
+    }
+
+    #[test]
+    fn test_bytes_hex_round_trip() {
+        let bytes: Bytes = "0x00ff10".parse().unwrap();
+        assert_eq!(bytes, Bytes(vec![0x00, 0xff, 0x10]));
+        assert_eq!(bytes.to_string(), "0x00ff10");
+        assert_eq!(bytes.to_string().parse::<Bytes>(), Ok(bytes));
+        assert_eq!("00ff10".parse::<Bytes>(), "0x00ff10".parse::<Bytes>());
+        assert_eq!("".parse::<Bytes>(), Ok(Bytes::default()));
+        assert!("0x0".parse::<Bytes>().is_err());
+        assert!("zz".parse::<Bytes>().is_err());
+    }
+
+    #[test]
+    fn test_bytes_ssz() {
+        let bytes = Bytes(vec![1, 2, 3]);
+        let encoded = bytes.to_ssz();
+        assert_eq!(encoded, [3, 0, 0, 0, 1, 2, 3]);
+        assert_eq!(Bytes::from_ssz(&encoded).unwrap(), bytes);
+        assert_eq!(Bytes::from_ssz(&[0, 0, 0, 0]).unwrap(), Bytes::default());
+        assert!(Bytes::from_ssz(&encoded[..6]).is_err());
+        assert!(Bytes::from_ssz(&[3, 0]).is_err());
+    }
+
+    #[test]
+    fn test_bytes_check_len() {
+        let bytes = Bytes(vec![0; 4]);
+        assert!(bytes.check_len(4).is_ok());
+        assert_eq!(
+            bytes.check_len(3),
+            Err("expected 3 bytes, got 4".to_string())
+        );
+    }
+
+    #[test]
+    fn test_bytes32_eq() {
+        assert_eq!(Bytes32::from([1; 32]), Bytes32::from([1; 32]));
+        assert_ne!(Bytes32::from([1; 32]), Bytes32::default());
+    }
 }
@@ -0,0 +1,148 @@
+mod common;
+
+use common::*;
+use std::fs;
+
+#[test]
+fn output_flag_writes_results_to_file() {
+    let dir = scratch_dir("output");
+    let output = scout(&dir, &["run", "--output", "results.txt"]);
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("state_root"));
+
+    let results = fs::read_to_string(dir.join("results.txt")).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(results.starts_with("state_root: 0x"));
+    assert!(results.contains("scout_blocks_processed_total 1\n"));
+}
+
+#[test]
+fn logs_go_to_stderr() {
+    let dir = scratch_dir("logs");
+    let output = scout(&dir, &["run"]);
+    fs::remove_dir_all(&dir).unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stdout.starts_with("state_root: 0x"), "{}", stdout);
+    assert!(stderr.contains("State changes"), "{}", stderr);
+    // Host calls are logged at debug level, below the default.
+    assert!(!stderr.contains("eth2_"), "{}", stderr);
+}
+
+#[test]
+fn inspect_reports_module_info() {
+    let dir = scratch_dir("inspect");
+    let output = scout(&dir, &["inspect", "--code", "phase2_helloworld.wasm"]);
+    fs::remove_dir_all(&dir).unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("imports: 3\n"), "{}", stdout);
+    assert!(stdout.contains("exports: 2\n"), "{}", stdout);
+}
+
+#[test]
+fn gas_limit_flag_bounds_execution() {
+    let dir = scratch_dir("gas");
+    let output = scout(&dir, &["run"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("gas used: "));
+
+    let output = scout_unchecked(&dir, &["run", "--gas-limit", "1"]);
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(output.status.code(), Some(4));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("out of gas"), "{}", stderr);
+}
+
+#[test]
+fn exit_codes_distinguish_failures() {
+    let dir = scratch_dir("exit");
+    let bad_args = scout_unchecked(&dir, &["run", "--pre-state", "00"]);
+    let missing_file = scout_unchecked(&dir, &["run", "--code", "missing.wasm"]);
+    let execution = scout_unchecked(&dir, &["run", "--data", "42"]);
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(bad_args.status.code(), Some(2));
+    assert_eq!(missing_file.status.code(), Some(3));
+    assert_eq!(execution.status.code(), Some(4));
+}
+
+#[test]
+fn built_modules_execute() {
+    let dir = scratch_dir("modules");
+    fs::write(dir.join("loadstore.wasm"), loadstore_module()).unwrap();
+    fs::write(dir.join("trap.wasm"), trap_module()).unwrap();
+
+    let pre_state = format!("0x{}", "11".repeat(32));
+    let loadstore = scout_unchecked(
+        &dir,
+        &["run", "--code", "loadstore.wasm", "--pre-state", &pre_state],
+    );
+    let trapped = scout_unchecked(&dir, &["run", "--code", "trap.wasm"]);
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(loadstore.status.success(), "{:?}", loadstore);
+    assert_eq!(trapped.status.code(), Some(4));
+}
+
+#[test]
+fn beacon_root_fingerprints_scripts() {
+    use sha2::{Digest, Sha256};
+
+    let dir = scratch_dir("beacon-root");
+    let scripts = dir.join("scripts");
+    fs::create_dir_all(&scripts).unwrap();
+    let a = loadstore_module();
+    let b = empty_main_module();
+    fs::write(scripts.join("a.wasm"), &a).unwrap();
+    fs::write(scripts.join("b.wasm"), &b).unwrap();
+    let output = scout(&dir, &["beacon-root", "--dir", "scripts"]);
+    fs::remove_dir_all(&dir).unwrap();
+
+    // The root of two leaves is the hash of their concatenation.
+    let leaves = [Sha256::digest(&a), Sha256::digest(&b)].concat();
+    let expected: String = Sha256::digest(leaves)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains(&format!("beacon_root: 0x{}\n", expected)),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn replay_rejects_unknown_env() {
+    let dir = scratch_dir("replay-env");
+    // A beacon state without scripts and one block, with an empty body, for environment 5.
+    fs::write(dir.join("beacon.bin"), 0u32.to_le_bytes()).unwrap();
+    let block = [&5u64.to_le_bytes()[..], &0u32.to_le_bytes()[..]].concat();
+    fs::write(dir.join("blocks.bin"), block).unwrap();
+    let replay = |extra: &[&str]| {
+        let args = [
+            &[
+                "replay",
+                "--input",
+                "blocks.bin",
+                "--beacon-state",
+                "beacon.bin",
+            ][..],
+            extra,
+        ]
+        .concat();
+        scout_unchecked(&dir, &args)
+    };
+    let stopped = replay(&[]);
+    let skipped = replay(&["--skip-errors"]);
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(stopped.status.code(), Some(4), "{:?}", stopped);
+    assert!(skipped.status.success(), "{:?}", skipped);
+    let stderr = String::from_utf8_lossy(&skipped.stderr);
+    assert!(
+        stderr.contains("skipped block 0: unknown execution environment 5"),
+        "{}",
+        stderr
+    );
+}
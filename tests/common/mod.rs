@@ -0,0 +1,39 @@
+//! Helpers shared by the integration tests.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+// The modules the unit tests are built from, shared so the two suites do not drift apart.
+// Each test binary only uses some of them.
+#[allow(dead_code)]
+#[path = "../../src/test_modules.rs"]
+mod test_modules;
+pub use test_modules::*;
+
+/// A scratch directory holding the helloworld fixture as `phase2_helloworld.wasm`.
+pub fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("scout-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/helloworld.wat");
+    fs::write(
+        dir.join("phase2_helloworld.wasm"),
+        wat::parse_file(fixture).unwrap(),
+    )
+    .unwrap();
+    dir
+}
+
+pub fn scout_unchecked(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_phase2-scout"))
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap()
+}
+
+pub fn scout(dir: &Path, args: &[&str]) -> Output {
+    let output = scout_unchecked(dir, args);
+    assert!(output.status.success(), "{:?}", output);
+    output
+}
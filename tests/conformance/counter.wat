@@ -0,0 +1,13 @@
+;; Adds the little-endian u64 carried by the block to the counter held in the first
+;; 8 bytes of the state, wrapping on overflow. The rest of the state is kept.
+(module
+  (import "env" "eth2_loadPreState" (func $loadPreState (param i32)))
+  (import "env" "eth2_blockDataCopy" (func $blockDataCopy (param i32 i32 i32)))
+  (import "env" "eth2_savePostState" (func $savePostState (param i32)))
+  (memory (export "memory") 1)
+  (func (export "main")
+    (call $loadPreState (i32.const 0))
+    (call $blockDataCopy (i32.const 32) (i32.const 0) (i32.const 8))
+    (i64.store (i32.const 0)
+      (i64.add (i64.load (i32.const 0)) (i64.load (i32.const 32))))
+    (call $savePostState (i32.const 0))))
@@ -0,0 +1,6 @@
+;; Unbounded recursion, which must exhaust the pinned interpreter stack rather than the
+;; native one.
+(module
+  (memory (export "memory") 1)
+  (func $recurse (call $recurse))
+  (func (export "main") (call $recurse)))
@@ -0,0 +1,2 @@
+;; A module with nothing in it, neither the memory nor the entry point execution expects.
+(module)
@@ -0,0 +1,5 @@
+;; A memory claiming more pages than fit in a 32-bit address space, which must not reach
+;; the allocator.
+(module
+  (memory (export "memory") 65537)
+  (func (export "main")))
@@ -0,0 +1,3 @@
+;; An entry point without the memory export the host functions read and write.
+(module
+  (func (export "main")))
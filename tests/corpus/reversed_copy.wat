@@ -0,0 +1,6 @@
+;; A block data copy whose length wraps the 32-bit range, so its end comes before its start.
+(module
+  (import "env" "eth2_blockDataCopy" (func $blockDataCopy (param i32 i32 i32)))
+  (memory (export "memory") 1)
+  (func (export "main")
+    (call $blockDataCopy (i32.const 0) (i32.const 1) (i32.const -1))))
@@ -0,0 +1,6 @@
+;; A start function, on which instantiation used to panic in `assert_no_start`.
+(module
+  (memory (export "memory") 1)
+  (func $init)
+  (start $init)
+  (func (export "main")))
@@ -0,0 +1,12 @@
+;; Hand-written equivalent of scripts/helloworld: the pre-state is saved unchanged
+;; as the post-state, and the block is expected to be empty.
+(module
+  (import "env" "eth2_loadPreState" (func $loadPreState (param i32)))
+  (import "env" "eth2_blockDataSize" (func $blockDataSize (result i32)))
+  (import "env" "eth2_savePostState" (func $savePostState (param i32)))
+  (memory (export "memory") 1)
+  (func (export "main")
+    (call $loadPreState (i32.const 0))
+    (if (i32.ne (call $blockDataSize) (i32.const 0))
+      (then unreachable))
+    (call $savePostState (i32.const 0))))